@@ -0,0 +1,573 @@
+use crate::clipboard::{ClipboardBackend, Selection, foreground_app, notify};
+use crate::config::{Config, SaveMode, debug_log};
+use crate::history::{ClipHistory, Entry, now_timestamp};
+use crate::images;
+use crate::process::{shell_command, wait_with_timeout};
+use std::fmt::Display;
+use std::io::{Read, Write};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, spawn};
+use std::time::{Duration, Instant};
+
+/// What [`run`] shares with the rest of the program while it watches the clipboard.
+pub struct Capture {
+    /// Bumped whenever a capture changes the history, so a UI knows to reload it.
+    pub captured: AtomicUsize,
+    /// What is on the clipboard right now, whether or not it was stored.
+    pub live: Mutex<Option<String>>,
+    /// Set while the clipboard can't be reached, and while `on_capture_command` keeps failing.
+    pub notice: Mutex<Option<String>>,
+    /// clipb's own writes, which aren't captured again.
+    pub restored: Restored,
+    // Cleared by `stop`; `run` finishes its current capture and returns.
+    running: AtomicBool,
+}
+
+impl Default for Capture {
+    fn default() -> Self {
+        Capture {
+            captured: AtomicUsize::new(0),
+            live: Mutex::new(None),
+            notice: Mutex::new(None),
+            restored: Restored::default(),
+            running: AtomicBool::new(true),
+        }
+    }
+}
+
+impl Capture {
+    /// Makes [`run`] return, and stops it retrying a clipboard it can't reach.
+    pub fn stop(&self) {
+        self.running.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Text clipb itself last put on the clipboard and on the primary selection. Set the matching
+/// one before writing there, and clear it if the write fails, so [`run`] can tell the write from
+/// the user's copies and selections. Images are marked by their stored file name.
+#[derive(Default)]
+pub struct Restored {
+    pub clipboard: Mutex<Option<String>>,
+    pub primary: Mutex<Option<String>>,
+}
+
+const CONNECT_RETRY_START: Duration = Duration::from_millis(250);
+const CONNECT_RETRY_MAX: Duration = Duration::from_secs(30);
+
+/// Stores what is copied into `history` until [`Capture::stop`] is called. The clipboard comes
+/// from `connect`, retried with backoff (and reported through [`Capture::notice`]) while it
+/// fails. Read errors go to `on_error`, once each until a read succeeds.
+pub fn run<B: ClipboardBackend, E: Display>(
+    capture: &Arc<Capture>,
+    history: &Arc<Mutex<ClipHistory>>,
+    config: &Config,
+    connect: impl FnMut() -> Result<B, E>,
+    on_error: impl Fn(String),
+) {
+    let running = &capture.running;
+    let Some(mut clipboard) = connect_clipboard(connect, &capture.notice, running) else {
+        return;
+    };
+    let mut last_text: Option<String> = None;
+    let mut last_flush = Instant::now();
+    // A failure that repeats on every poll is reported once, until a read succeeds.
+    let mut last_error: Option<String> = None;
+    // Captures in a burst share one notification; a failed one stops further attempts.
+    let notifier = config
+        .notify_on_capture
+        .then(|| Arc::new(Notifier::default()));
+    // Highlighting text changes the primary selection with every drag, so a selection is
+    // only stored once it has held still for a poll. One clipb restored there is skipped
+    // like a clipboard restore, and so is one the history already has (because it was also
+    // copied with Ctrl+C, say).
+    let mut primary_seen: Option<String> = None;
+    let mut primary_stored: Option<String> = None;
+    if config.capture_primary {
+        clipboard.watch_primary();
+    }
+
+    while running.load(Ordering::Relaxed) {
+        if config.save_mode == SaveMode::Interval
+            && last_flush.elapsed() >= Duration::from_secs(config.save_interval_secs)
+        {
+            history.lock().unwrap().flush();
+            last_flush = Instant::now();
+        }
+        let flush_due = (config.save_mode == SaveMode::Interval)
+            .then(|| last_flush + Duration::from_secs(config.save_interval_secs));
+
+        if config.capture_primary
+            && let Ok(text) = clipboard.get_primary_text()
+        {
+            if primary_seen.as_ref() == Some(&text) && primary_stored.as_ref() != Some(&text) {
+                primary_stored = Some(text.clone());
+                let is_restore = capture.restored.primary.lock().unwrap().take().as_deref()
+                    == Some(text.as_str());
+                let content = config.captured_text(&text).into_owned();
+                let known = history
+                    .lock()
+                    .unwrap()
+                    .entries()
+                    .iter()
+                    .any(|e| e.image.is_none() && e.content == content);
+                if !is_restore && config.keeps_text(&content) && !known {
+                    let source = if config.track_source {
+                        foreground_app()
+                    } else {
+                        None
+                    };
+                    let entry = Entry {
+                        timestamp: now_timestamp(config.timestamp_utc),
+                        content,
+                        source,
+                        count: 1,
+                        source_selection: Some(Selection::Primary),
+                        ..Default::default()
+                    };
+                    if let Some(command) = config.on_capture_command.clone() {
+                        spawn_capture_hook(
+                            command,
+                            entry,
+                            capture,
+                            history,
+                            notifier.clone(),
+                            config,
+                        );
+                    } else {
+                        if let Some(notifier) = &notifier {
+                            notifier.saved(&entry);
+                        }
+                        history.lock().unwrap().push(entry, config);
+                        capture.captured.fetch_add(1, Ordering::Relaxed);
+                        compact_if_needed(history);
+                    }
+                }
+            }
+            primary_seen = Some(text);
+        } else {
+            primary_seen = None;
+        }
+        // A selection still settling is read again after a poll interval, change or not.
+        let settle_due = (primary_seen.is_some() && primary_seen != primary_stored)
+            .then(|| Instant::now() + Duration::from_millis(config.poll_interval_ms));
+        let deadline = flush_due.into_iter().chain(settle_due).min();
+
+        // A clipboard holding only an image makes `get_text` fail, so images are tried next.
+        // They are stored as files and listed under a placeholder, keyed by their file name.
+        let mut failure = None;
+        let clip = match clipboard.get_text() {
+            Ok(text) => Some((text, None)),
+            Err(text_error) => match clipboard.get_image() {
+                Ok(image) => match images::store(&image) {
+                    Ok(name) => Some((
+                        format!("[image {}×{}]", image.width, image.height),
+                        Some(name),
+                    )),
+                    Err(e) => {
+                        failure = Some(format!("Couldn't store the copied image: {}", e));
+                        None
+                    }
+                },
+                Err(_) => {
+                    failure = Some(format!("Couldn't read the clipboard: {}", text_error));
+                    None
+                }
+            },
+        };
+
+        if failure != last_error {
+            if let Some(e) = &failure {
+                on_error(e.clone());
+            }
+            last_error = failure;
+        }
+
+        if let Some((current_text, image)) = clip {
+            *capture.live.lock().unwrap() = Some(current_text.clone());
+            let key = image.clone().unwrap_or_else(|| current_text.clone());
+            // Changes are spotted on the clipboard's own text; what is stored is trimmed.
+            let current_text = match image {
+                Some(_) => current_text,
+                None => config.captured_text(&current_text).into_owned(),
+            };
+
+            // Stray one-character selections and the like aren't worth keeping.
+            if image.is_none() && !config.keeps_text(&current_text) {
+                wait_for_clip(&mut clipboard, config, deadline, running);
+                continue;
+            }
+
+            if last_text.as_ref() != Some(&key) {
+                let timestamp = now_timestamp(config.timestamp_utc);
+
+                let source = if config.track_source {
+                    foreground_app()
+                } else {
+                    None
+                };
+
+                // Self-capture rules: the first change the poll thread sees after clipb
+                // restores a clip consumes the marker. If that change is the restored clip it
+                // is never stored as a new entry; with `refresh_on_restore` the existing entry
+                // is moved to the top and its count bumped (even without `dedup`), otherwise
+                // it is ignored. Anything else is an external copy and goes through
+                // `ClipHistory::push`, where `dedup` applies. Polling can't tell an external
+                // re-copy of the same clip from clipb's own write, so one arriving before the
+                // next poll is treated as a restore.
+                let is_restore = capture.restored.clipboard.lock().unwrap().take().as_deref()
+                    == Some(key.as_str());
+                last_text = Some(key);
+
+                // Browsers and editors put an HTML form next to the text; keep it when offered.
+                let html = match image {
+                    Some(_) => None,
+                    None => clipboard.get_html().ok().filter(|html| !html.is_empty()),
+                };
+                let entry = Entry {
+                    timestamp,
+                    content: current_text,
+                    source,
+                    count: 1,
+                    image,
+                    html,
+                    source_selection: config.capture_primary.then_some(Selection::Clipboard),
+                    ..Default::default()
+                };
+
+                // The hook decides whether and what to store, on a thread of its own.
+                if !is_restore
+                    && entry.image.is_none()
+                    && let Some(command) = config.on_capture_command.clone()
+                {
+                    spawn_capture_hook(command, entry, capture, history, notifier.clone(), config);
+                    wait_for_clip(&mut clipboard, config, deadline, running);
+                    continue;
+                }
+
+                if !is_restore && let Some(notifier) = &notifier {
+                    notifier.saved(&entry);
+                }
+                let mut hist = history.lock().unwrap();
+                if is_restore {
+                    if config.refresh_on_restore && hist.bump(&entry) {
+                        capture.captured.fetch_add(1, Ordering::Relaxed);
+                    }
+                } else {
+                    hist.push(entry, config);
+                    capture.captured.fetch_add(1, Ordering::Relaxed);
+                }
+                drop(hist);
+                compact_if_needed(history);
+            }
+        }
+        wait_for_clip(&mut clipboard, config, deadline, running);
+    }
+}
+
+// `notify_on_capture` notifications, shared by the poll loop and `on_capture_command` runs.
+#[derive(Default)]
+struct Notifier {
+    last: Mutex<Option<Instant>>,
+    // Set once a notification fails (no notification daemon, say); none are tried after that.
+    failed: Arc<AtomicBool>,
+}
+
+impl Notifier {
+    // Says `entry` was saved, unless another notification went out within `NOTIFY_MIN_GAP`.
+    // Sent from a thread of its own so a slow notifier holds nothing up.
+    fn saved(&self, entry: &Entry) {
+        let mut last = self.last.lock().unwrap();
+        if self.failed.load(Ordering::Relaxed)
+            || last.is_some_and(|at| at.elapsed() < NOTIFY_MIN_GAP)
+        {
+            return;
+        }
+        *last = Some(Instant::now());
+        let body = match entry.image {
+            Some(_) => "Saved an image".to_string(),
+            None => {
+                let first = entry.content.lines().next().unwrap_or("");
+                let mut body: String = first.chars().take(NOTIFY_PREVIEW_CHARS).collect();
+                if body.len() < entry.content.trim_end_matches(['\r', '\n']).len() {
+                    body.push('…');
+                }
+                format!("Saved clip: {}", body)
+            }
+        };
+        let failed = Arc::clone(&self.failed);
+        spawn(move || {
+            if !notify("clipb", &body) {
+                failed.store(true, Ordering::Relaxed);
+                debug_log("desktop notification failed; not trying again");
+            }
+        });
+    }
+}
+
+// `notify_on_capture` sends at most one notification this often, showing this much of the clip.
+const NOTIFY_MIN_GAP: Duration = Duration::from_secs(3);
+const NOTIFY_PREVIEW_CHARS: usize = 40;
+
+// Some compositors refuse clipboard connections for a while after login, so keep retrying with
+// exponential backoff instead of giving up, and report progress through `notice`.
+fn connect_clipboard<B, E: Display>(
+    mut connect: impl FnMut() -> Result<B, E>,
+    notice: &Mutex<Option<String>>,
+    running: &AtomicBool,
+) -> Option<B> {
+    let mut delay = CONNECT_RETRY_START;
+    let mut attempt = 1;
+    while running.load(Ordering::Relaxed) {
+        match connect() {
+            Ok(clipboard) => {
+                *notice.lock().unwrap() = None;
+                return Some(clipboard);
+            }
+            Err(e) => {
+                *notice.lock().unwrap() = Some(format!(
+                    "Clipboard unavailable ({}), retry {} in {:.1}s",
+                    e,
+                    attempt,
+                    delay.as_secs_f32()
+                ));
+                sleep_while_running(delay, running);
+                delay = (delay * 2).min(CONNECT_RETRY_MAX);
+                attempt += 1;
+            }
+        }
+    }
+    None
+}
+
+// Why `on_capture_command` didn't let a clip through: it asked for the clip to be dropped by
+// exiting non-zero, or it couldn't be run (or was stopped) and `Failed` says why.
+enum HookError {
+    Rejected,
+    Failed(String),
+}
+
+// Runs `on_capture_command` with the clip on its stdin and returns what it printed, decoded
+// lossily. A non-zero exit rejects the clip; one that outlives `timeout` is killed.
+fn run_capture_hook(command: &str, content: &str, timeout: Duration) -> Result<String, HookError> {
+    const WHAT: &str = "on_capture_command";
+    let mut child = shell_command(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| HookError::Failed(format!("{} failed: {}", WHAT, e)))?;
+    // Fed and drained on threads of their own, so a hook that writes before it has read all of
+    // a large clip can't deadlock against us.
+    if let Some(mut stdin) = child.stdin.take() {
+        let content = content.to_string();
+        spawn(move || stdin.write_all(content.as_bytes()));
+    }
+    let stdout = child.stdout.take().map(|mut stdout| {
+        spawn(move || {
+            let mut output = Vec::new();
+            let _ = stdout.read_to_end(&mut output);
+            output
+        })
+    });
+    match wait_with_timeout(child, WHAT, Some(timeout)).map_err(HookError::Failed)? {
+        status if status.success() => {
+            let output = stdout
+                .and_then(|reader| reader.join().ok())
+                .unwrap_or_default();
+            Ok(String::from_utf8_lossy(&output).into_owned())
+        }
+        _ => Err(HookError::Rejected),
+    }
+}
+
+// Runs `on_capture_command` on a captured text clip on a thread of its own, then stores it
+// (or what the hook printed) unless the hook rejected it.
+fn spawn_capture_hook(
+    command: String,
+    entry: Entry,
+    capture: &Arc<Capture>,
+    history: &Arc<Mutex<ClipHistory>>,
+    notifier: Option<Arc<Notifier>>,
+    config: &Config,
+) {
+    let capture = Arc::clone(capture);
+    let history = Arc::clone(history);
+    let hook_config = config.clone();
+    let timeout = Duration::from_millis(config.command_timeout_ms);
+    spawn(move || {
+        let mut entry = entry;
+        match run_capture_hook(&command, &entry.content, timeout) {
+            Ok(output) => {
+                *capture.notice.lock().unwrap() = None;
+                if hook_config.on_capture_replace {
+                    let Some(output) = replacement(&output, &hook_config) else {
+                        return;
+                    };
+                    if output != entry.content {
+                        entry.content = output;
+                        // The HTML no longer says the same thing.
+                        entry.html = None;
+                    }
+                }
+            }
+            Err(HookError::Rejected) => return,
+            Err(HookError::Failed(e)) => {
+                *capture.notice.lock().unwrap() = Some(e);
+                return;
+            }
+        }
+        if let Some(notifier) = &notifier {
+            notifier.saved(&entry);
+        }
+        history.lock().unwrap().push(entry, &hook_config);
+        capture.captured.fetch_add(1, Ordering::Relaxed);
+    });
+}
+
+// What `on_capture_replace` stores from a hook's output: the text as a clip copied with it
+// would be stored, or `None` if such a clip would be skipped.
+fn replacement(output: &str, config: &Config) -> Option<String> {
+    let output = config.captured_text(output);
+    config.keeps_text(&output).then(|| output.into_owned())
+}
+
+// The rewrite happens with the lock released so the UI never waits on it; it is dropped if
+// anything writes to the history in the meantime.
+fn compact_if_needed(history: &Mutex<ClipHistory>) {
+    let hist = history.lock().unwrap();
+    if !hist.needs_compaction() {
+        return;
+    }
+    let compaction = hist.start_compaction();
+    drop(hist);
+    let done = compaction.write().is_ok() && history.lock().unwrap().finish_compaction(compaction);
+    debug_log(if done {
+        "compacted history file"
+    } else {
+        "history compaction skipped"
+    });
+}
+
+// How long one `wait_for_change` call may block, so shutdown and interval saves aren't held up.
+const CHANGE_WAIT: Duration = Duration::from_millis(250);
+
+// Waits until the clipboard is worth reading again: until the backend reports a change, where
+// it can and `change_events` is on, else for `poll_interval_ms`. Returns early at `deadline`
+// and on shutdown.
+fn wait_for_clip(
+    clipboard: &mut impl ClipboardBackend,
+    config: &Config,
+    deadline: Option<Instant>,
+    running: &AtomicBool,
+) {
+    while config.change_events && running.load(Ordering::Relaxed) {
+        match clipboard.wait_for_change(CHANGE_WAIT) {
+            Some(true) => return,
+            Some(false) if deadline.is_some_and(|at| Instant::now() >= at) => return,
+            Some(false) => {}
+            None => break,
+        }
+    }
+    sleep_while_running(Duration::from_millis(config.poll_interval_ms), running);
+}
+
+// Sleeps in short steps so a shutdown never waits out a whole poll interval or retry delay.
+fn sleep_while_running(duration: Duration, running: &AtomicBool) {
+    const STEP: Duration = Duration::from_millis(50);
+    let mut left = duration;
+    while !left.is_zero() && running.load(Ordering::Relaxed) {
+        let step = left.min(STEP);
+        thread::sleep(step);
+        left -= step;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{TrimPolicy, data_path};
+    use std::collections::VecDeque;
+    use std::error::Error;
+    use std::fs;
+
+    // Hands out one clip per read and stops the capture once they run out. A clip marked as
+    // clipb's own is marked restored before it is read, as `copy_entry` would.
+    struct ScriptedClipboard {
+        clips: VecDeque<(&'static str, bool)>,
+        capture: Arc<Capture>,
+    }
+
+    impl ClipboardBackend for ScriptedClipboard {
+        fn get_text(&mut self) -> Result<String, Box<dyn Error + Send + Sync>> {
+            let Some((clip, restored)) = self.clips.pop_front() else {
+                self.capture.stop();
+                return Err("no more clips".into());
+            };
+            if restored {
+                *self.capture.restored.clipboard.lock().unwrap() = Some(clip.to_string());
+            }
+            Ok(clip.to_string())
+        }
+
+        fn set_text(&mut self, _text: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn hook_output_is_filtered_like_a_clip() {
+        let config = Config {
+            min_entry_chars: 3,
+            trim_policy: TrimPolicy::TrailingOnly,
+            ..Config::default()
+        };
+        assert_eq!(replacement("kept\n", &config), Some("kept".to_string()));
+        assert_eq!(replacement("ab\n", &config), None);
+        assert_eq!(replacement("\n\n", &config), None);
+    }
+
+    #[test]
+    fn copies_are_stored_and_restores_are_not() {
+        let config = Config {
+            history_filename: "capture-restores.json".to_string(),
+            poll_interval_ms: 1,
+            ..Config::default()
+        };
+        // Left over from an earlier run, the file would feed its clips into the history.
+        for file in ["capture-restores.json", "capture-restores.pinned.json"] {
+            let _ = fs::remove_file(data_path(file));
+        }
+        let history = Arc::new(Mutex::new(ClipHistory::load(&config, None)));
+        let capture = Arc::new(Capture::default());
+        let clipboard = ScriptedClipboard {
+            clips: [
+                ("copied", false),
+                ("restored", true),
+                ("copied again", false),
+            ]
+            .into(),
+            capture: Arc::clone(&capture),
+        };
+        let errors = Mutex::new(Vec::new());
+        let mut connect = Some(clipboard);
+        run(
+            &capture,
+            &history,
+            &config,
+            || connect.take().ok_or("connected twice"),
+            |e| errors.lock().unwrap().push(e),
+        );
+
+        let hist = history.lock().unwrap();
+        let stored: Vec<&str> = hist.entries().iter().map(|e| e.content.as_str()).collect();
+        assert_eq!(stored, ["copied", "copied again"]);
+        assert_eq!(capture.captured.load(Ordering::Relaxed), 2);
+        assert_eq!(
+            *capture.live.lock().unwrap(),
+            Some("copied again".to_string())
+        );
+        assert_eq!(errors.into_inner().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,327 @@
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::error::Error;
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+/// Where clips are read from and restored to. [`SystemClipboard`] is the real one; other
+/// implementations let the capture logic run without a display.
+pub trait ClipboardBackend {
+    /// The clipboard's text, as it is. Fails when it holds none (only an image, say).
+    fn get_text(&mut self) -> Result<String, Box<dyn Error + Send + Sync>>;
+
+    /// Replaces the clipboard's contents with `text`.
+    fn set_text(&mut self, text: &str) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    /// Image contents, for backends that support them.
+    fn get_image(&mut self) -> Result<Image, Box<dyn Error + Send + Sync>> {
+        Err("images are not supported".into())
+    }
+
+    /// Puts `image` on the clipboard, for backends that support images.
+    fn set_image(&mut self, _image: &Image) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Err("images are not supported".into())
+    }
+
+    /// The HTML form of the current clip, for backends that expose one.
+    fn get_html(&mut self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        Err("HTML is not supported".into())
+    }
+
+    /// Offers `html` with `alt_text` as the plain-text fallback. Backends without HTML support
+    /// just set the plain text.
+    fn set_html(
+        &mut self,
+        _html: &str,
+        alt_text: &str,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.set_text(alt_text)
+    }
+
+    /// Sets the X11/Wayland primary selection (what middle-click pastes), where there is one.
+    fn set_primary_text(&mut self, _text: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Err("the primary selection is not supported on this platform".into())
+    }
+
+    /// The text of the X11/Wayland primary selection (whatever is highlighted), where there is one.
+    fn get_primary_text(&mut self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        Err("the primary selection is not supported on this platform".into())
+    }
+
+    /// Blocks until the clipboard changes, for at most `timeout`, and says whether it did.
+    /// `None` means the backend can't be told about changes and the caller has to poll.
+    fn wait_for_change(&mut self, _timeout: Duration) -> Option<bool> {
+        None
+    }
+
+    /// Makes [`wait_for_change`](Self::wait_for_change) report changes to the primary
+    /// selection too, for callers reading both. Backends that can't watch it return `None`
+    /// from then on, so the caller polls.
+    fn watch_primary(&mut self) {}
+}
+
+/// Which selection a clip was captured from: the clipboard (`Ctrl+C`) or, on X11/Wayland, the
+/// primary selection (highlighted text, pasted with a middle click).
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Selection {
+    Clipboard,
+    Primary,
+}
+
+/// Uncompressed image contents: `width * height` RGBA pixels, row by row.
+#[derive(Clone)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub rgba: Vec<u8>,
+}
+
+/// The platform clipboard, through `arboard`. On Wayland, changes are watched for with
+/// `wl-paste --watch` (from wl-clipboard) when it is installed, and the primary selection's with
+/// `wl-paste --primary --watch` once [`watch_primary`](ClipboardBackend::watch_primary) asks.
+pub struct SystemClipboard {
+    clipboard: arboard::Clipboard,
+    watcher: Watcher,
+    watch_primary: bool,
+}
+
+impl SystemClipboard {
+    pub fn new() -> Result<Self, arboard::Error> {
+        Ok(SystemClipboard {
+            clipboard: arboard::Clipboard::new()?,
+            watcher: Watcher::NotStarted,
+            watch_primary: false,
+        })
+    }
+}
+
+// Started on the first `wait_for_change`, so one-off uses of the clipboard never spawn it.
+enum Watcher {
+    NotStarted,
+    Running(ChangeWatch),
+    Unavailable,
+}
+
+// `wl-paste --watch` children, one per watched selection, that print a line each time their
+// selection changes, and the channel reader threads forward those lines to: `true` for a
+// change, `false` once a child has exited and its selection is no longer watched.
+struct ChangeWatch {
+    children: Vec<Child>,
+    changes: Receiver<bool>,
+}
+
+impl ChangeWatch {
+    fn start(primary: bool) -> Option<ChangeWatch> {
+        if !cfg!(target_os = "linux") || std::env::var_os("WAYLAND_DISPLAY").is_none() {
+            return None;
+        }
+        let (sender, changes) = mpsc::channel();
+        // Children already started are stopped by `drop` if a later one fails.
+        let mut watch = ChangeWatch {
+            children: Vec::new(),
+            changes,
+        };
+        let selections: &[&[&str]] = if primary {
+            &[&[], &["--primary"]]
+        } else {
+            &[&[]]
+        };
+        for selection in selections {
+            let mut child = Command::new("wl-paste")
+                .args(*selection)
+                .args(["--watch", "echo"])
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .ok()?;
+            let stdout = child.stdout.take();
+            watch.children.push(child);
+            let stdout = stdout?;
+            let sender = sender.clone();
+            std::thread::spawn(move || {
+                for _ in BufReader::new(stdout).lines() {
+                    if sender.send(true).is_err() {
+                        return;
+                    }
+                }
+                let _ = sender.send(false);
+            });
+        }
+        Some(watch)
+    }
+}
+
+impl Drop for ChangeWatch {
+    fn drop(&mut self) {
+        for child in &mut self.children {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+impl ClipboardBackend for SystemClipboard {
+    fn get_text(&mut self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        Ok(self.clipboard.get_text()?)
+    }
+
+    fn set_text(&mut self, text: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Ok(self.clipboard.set_text(text)?)
+    }
+
+    fn get_image(&mut self) -> Result<Image, Box<dyn Error + Send + Sync>> {
+        let image = self.clipboard.get_image()?;
+        Ok(Image {
+            width: image.width,
+            height: image.height,
+            rgba: image.bytes.into_owned(),
+        })
+    }
+
+    fn set_image(&mut self, image: &Image) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Ok(self.clipboard.set_image(arboard::ImageData {
+            width: image.width,
+            height: image.height,
+            bytes: Cow::Borrowed(&image.rgba),
+        })?)
+    }
+
+    fn get_html(&mut self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        Ok(self.clipboard.get().html()?)
+    }
+
+    fn set_html(&mut self, html: &str, alt_text: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Ok(self.clipboard.set_html(html, Some(alt_text))?)
+    }
+
+    fn wait_for_change(&mut self, timeout: Duration) -> Option<bool> {
+        if let Watcher::NotStarted = self.watcher {
+            self.watcher = ChangeWatch::start(self.watch_primary)
+                .map_or(Watcher::Unavailable, Watcher::Running);
+        }
+        let Watcher::Running(watch) = &self.watcher else {
+            return None;
+        };
+        match watch.changes.recv_timeout(timeout) {
+            Ok(true) => {
+                // A burst of changes is handled with a single read.
+                let mut watching = true;
+                while let Ok(change) = watch.changes.try_recv() {
+                    watching &= change;
+                }
+                if !watching {
+                    self.watcher = Watcher::Unavailable;
+                }
+                Some(true)
+            }
+            Err(RecvTimeoutError::Timeout) => Some(false),
+            // A `wl-paste` exited, e.g. because the compositor lacks the protocol it needs.
+            Ok(false) | Err(RecvTimeoutError::Disconnected) => {
+                self.watcher = Watcher::Unavailable;
+                None
+            }
+        }
+    }
+
+    fn watch_primary(&mut self) {
+        if !self.watch_primary {
+            self.watch_primary = true;
+            // A running watch covers the clipboard alone; the next wait starts one for both.
+            self.watcher = Watcher::NotStarted;
+        }
+    }
+
+    #[cfg(all(
+        unix,
+        not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))
+    ))]
+    fn set_primary_text(&mut self, text: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        use arboard::{LinuxClipboardKind, SetExtLinux};
+        Ok(self
+            .clipboard
+            .set()
+            .clipboard(LinuxClipboardKind::Primary)
+            .text(text)?)
+    }
+
+    #[cfg(all(
+        unix,
+        not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))
+    ))]
+    fn get_primary_text(&mut self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        use arboard::{GetExtLinux, LinuxClipboardKind};
+        Ok(self
+            .clipboard
+            .get()
+            .clipboard(LinuxClipboardKind::Primary)
+            .text()?)
+    }
+}
+
+/// Name of the application that owns the focused window, where the platform lets us ask.
+#[cfg(target_os = "linux")]
+pub fn foreground_app() -> Option<String> {
+    let output = Command::new("xdotool")
+        .args(["getactivewindow", "getwindowclassname"])
+        .output()
+        .ok()?;
+    let name = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (output.status.success() && !name.is_empty()).then_some(name)
+}
+
+/// Name of the application that owns the focused window, where the platform lets us ask.
+#[cfg(target_os = "macos")]
+pub fn foreground_app() -> Option<String> {
+    let output = Command::new("osascript")
+        .args([
+            "-e",
+            "tell application \"System Events\" to get name of first application process whose frontmost is true",
+        ])
+        .output()
+        .ok()?;
+    let name = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (output.status.success() && !name.is_empty()).then_some(name)
+}
+
+/// Name of the application that owns the focused window, where the platform lets us ask.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn foreground_app() -> Option<String> {
+    None
+}
+
+/// Shows a desktop notification through `notify-send`. Returns false when it couldn't be
+/// shown, e.g. with no notification daemon running.
+#[cfg(target_os = "linux")]
+pub fn notify(summary: &str, body: &str) -> bool {
+    Command::new("notify-send")
+        .args(["--app-name=clipb", "--expire-time=2000", summary, body])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Shows a desktop notification through Notification Center. Returns false when it couldn't
+/// be shown.
+#[cfg(target_os = "macos")]
+pub fn notify(summary: &str, body: &str) -> bool {
+    // `{:?}` quotes and escapes the strings the way AppleScript string literals expect.
+    let script = format!("display notification {:?} with title {:?}", body, summary);
+    Command::new("osascript")
+        .args(["-e", &script])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Shows a desktop notification, where the platform lets us. Returns false when it couldn't be
+/// shown.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn notify(_summary: &str, _body: &str) -> bool {
+    false
+}
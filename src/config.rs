@@ -0,0 +1,671 @@
+use crate::transform::{Segment, template_segments};
+use dirs::config_dir;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Settings read from `config.json` in the data directory. Missing fields take their defaults.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Config {
+    /// Most entries kept; the oldest unpinned ones are pruned past it.
+    pub max_history: usize,
+    /// Largest the history file may get, in bytes; the oldest unpinned entries are pruned to
+    /// stay under it. 0 leaves only `max_history`.
+    pub max_file_bytes: usize,
+    /// How often the clipboard is read when changes can't be waited for (see `change_events`).
+    pub poll_interval_ms: u64,
+    /// Read the clipboard only when the platform says it changed, where it can (Wayland, with
+    /// wl-clipboard installed), instead of every `poll_interval_ms`.
+    pub change_events: bool,
+    /// Which clips count as empty and aren't stored.
+    pub skip_empty: SkipEmpty,
+    /// Text clips with fewer characters than this are not stored. 0 keeps everything.
+    pub min_entry_chars: usize,
+    /// Record the application that owned the focused window with each clip.
+    pub track_source: bool,
+    /// List entries made only of whitespace, which are stored either way.
+    pub show_whitespace_entries: bool,
+    /// Named histories, each in a file of its own and switched between with Tab. Empty uses
+    /// `history_filename` alone.
+    pub histories: Vec<String>,
+    /// Bytes of an entry the preview pane shows before pointing to `v` for the rest.
+    pub preview_max_bytes: usize,
+    /// Rows of an entry the preview pane shows before pointing to `v` for the rest, counting
+    /// wrapped lines. 0 fills the pane.
+    pub preview_max_lines: usize,
+    /// Lines shown above the first match when the preview follows a search.
+    pub match_context_lines: usize,
+    /// Most search results listed, in the current sort order; the rest are counted under the
+    /// list until the query is narrowed. 0 lists them all.
+    pub max_search_results: usize,
+    /// Say in the preview title when the selected entry is nearly the same as the one above or
+    /// below it. Costs an edit-distance comparison per frame.
+    pub similarity_hint: bool,
+    /// Stamp entries in UTC instead of local time.
+    pub timestamp_utc: bool,
+    /// Quit once an entry has been copied.
+    pub exit_on_select: bool,
+    /// Quit, copying nothing, once no key has been pressed for this many seconds, so a picker
+    /// opened from a hotkey doesn't linger. 0 waits forever.
+    pub idle_timeout_secs: u64,
+    /// Check new clips against the whole history, not just the last `dedup_window` entries.
+    pub dedup: bool,
+    /// How many of the newest entries a new clip is checked against before it is appended;
+    /// a match is moved to the top instead. Ignored when `dedup` checks the whole history.
+    pub dedup_window: usize,
+    /// What happens to a new clip that matches a pinned entry.
+    pub pinned_match: PinnedMatch,
+    /// Whether clips with the same text but different HTML count as the same clip.
+    pub html_match: HtmlMatch,
+    /// Which entry goes when `max_history` or `max_file_bytes` is exceeded.
+    pub eviction_policy: EvictionPolicy,
+    /// Move an entry to the top and bump its count when it is copied back out, instead of
+    /// leaving it where it is.
+    pub refresh_on_restore: bool,
+    /// Draw without colors, using bold and reverse video instead. `NO_COLOR` does the same.
+    pub no_color: bool,
+    /// Draw on the terminal's alternate screen. When off, the UI is drawn in a band at the
+    /// bottom of the normal screen and its last frame stays visible after exit.
+    pub alternate_screen: bool,
+    /// Start with only the list shown, for narrow splits; `m` toggles it.
+    pub compact: bool,
+    /// Name of the history file inside the data directory. Named histories and the pinned
+    /// file are derived from it.
+    pub history_filename: String,
+    /// Shell command run after `P` copies an entry, to paste it into the focused window
+    /// (e.g. `xdotool key --clearmodifiers ctrl+v` or `wtype -M ctrl v`).
+    pub paste_command: Option<String>,
+    /// Which selection `Enter` (and `P`) restores text to. Images always go to the clipboard.
+    pub restore_target: RestoreTarget,
+    /// Also capture the X11/Wayland primary selection (highlighted text), tagging each entry
+    /// with the selection it came from. Off means the clipboard only.
+    pub capture_primary: bool,
+    /// Shell command each captured text clip is piped to before it is stored. Exiting
+    /// non-zero drops the clip; see `on_capture_replace` for its output.
+    pub on_capture_command: Option<String>,
+    /// Store what `on_capture_command` prints instead of the clip. Otherwise its output is
+    /// ignored.
+    pub on_capture_replace: bool,
+    /// How long `paste_command` and `on_capture_command` may run before they are stopped.
+    pub command_timeout_ms: u64,
+    /// Remove ANSI escape sequences from text as it is copied back out.
+    pub strip_ansi_on_copy: bool,
+    /// Drop one trailing newline from text as it is copied back out.
+    pub strip_trailing_newline_on_copy: bool,
+    /// Whitespace trimmed from text clips as they are captured.
+    pub trim_policy: TrimPolicy,
+    /// Apply `trim_policy` as entries are copied back out instead, storing clips untrimmed.
+    pub trim_on_copy: bool,
+    /// Line endings text clips are converted to as they are captured.
+    pub line_endings: LineEndings,
+    /// Convert line endings as entries are copied back out instead, storing clips as copied.
+    pub line_endings_on_copy: bool,
+    /// What `r` copies instead of an entry's content. `{timestamp}`, `{content}` (its first
+    /// line, shortened), `{label}` and `{source}` are filled in.
+    pub reference_template: String,
+    /// Ask before deleting entries, clearing the history or pruning.
+    pub confirm_destructive: bool,
+    /// Shown in place of the list while the history is empty.
+    pub empty_message: String,
+    /// When captured clips reach the history file.
+    pub save_mode: SaveMode,
+    /// How often `SaveMode::Interval` writes pending clips out.
+    pub save_interval_secs: u64,
+    /// Write the history file on a thread of its own, so a slow disk (a network share, a
+    /// cloud-synced folder) never holds up capturing. Writes that pile up are merged.
+    pub background_saves: bool,
+    /// How each row of the list is laid out; see [`LIST_TEMPLATE_FIELDS`]. The optional
+    /// fields (`{pin}`, `{label}`, `{tag}`, `{count}`, `{source}`) carry their own spacing
+    /// and are empty when they don't apply.
+    pub list_template: String,
+    /// Show the list as aligned index, time and content columns instead of `list_template`
+    /// rows, when the list is wide enough.
+    pub list_columns: bool,
+    /// Order of the list until another is picked with `S`, which is then remembered.
+    pub sort: SortOrder,
+    /// Entry the cursor starts on when the list opens.
+    pub initial_selection: InitialSelection,
+    /// Put a header (Today, Yesterday, the date) above each day's entries while the list is
+    /// sorted by recency.
+    pub group_by_date: bool,
+    /// Let 1-9 copy the top nine entries, which are marked `[1]`..`[9]` in the list.
+    pub quick_copy: bool,
+    /// Pin entries once they have been copied this many times. 0 never pins automatically.
+    pub auto_pin_uses: u32,
+    /// Record every entry copied back out in `usage.json`; see [`crate::usage`].
+    pub log_usage: bool,
+    /// Show a desktop notification when a clip is captured, at most one every few seconds.
+    pub notify_on_capture: bool,
+    /// Port of the local HTTP API on 127.0.0.1 (see [`crate::server`]). 0 leaves it off.
+    pub server_port: u16,
+    /// What API requests must send as `Authorization: Bearer <token>`. Needed for
+    /// `server_port`.
+    pub server_token: Option<String>,
+    /// Browse without capturing clips or writing anything to the history files; usually set
+    /// for one run with `clipb view` or `--read-only`.
+    pub read_only: bool,
+}
+
+/// Placeholders accepted in `list_template`.
+pub const LIST_TEMPLATE_FIELDS: &[&str] = &[
+    "index", "pin", "time", "label", "tag", "preview", "chars", "count", "source",
+];
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_history: 200,
+            max_file_bytes: 0,
+            poll_interval_ms: 300,
+            change_events: true,
+            skip_empty: SkipEmpty::Newlines,
+            min_entry_chars: 0,
+            track_source: false,
+            show_whitespace_entries: true,
+            histories: Vec::new(),
+            preview_max_bytes: 64 * 1024,
+            preview_max_lines: 0,
+            match_context_lines: 2,
+            max_search_results: 500,
+            similarity_hint: false,
+            timestamp_utc: false,
+            exit_on_select: false,
+            idle_timeout_secs: 0,
+            dedup: false,
+            dedup_window: 1,
+            pinned_match: PinnedMatch::Store,
+            html_match: HtmlMatch::Merge,
+            eviction_policy: EvictionPolicy::Fifo,
+            refresh_on_restore: false,
+            no_color: false,
+            alternate_screen: true,
+            compact: false,
+            history_filename: "history.json".to_string(),
+            paste_command: None,
+            restore_target: RestoreTarget::Clipboard,
+            capture_primary: false,
+            on_capture_command: None,
+            on_capture_replace: false,
+            command_timeout_ms: 5000,
+            strip_ansi_on_copy: false,
+            strip_trailing_newline_on_copy: false,
+            trim_policy: TrimPolicy::None,
+            trim_on_copy: false,
+            line_endings: LineEndings::Preserve,
+            line_endings_on_copy: false,
+            reference_template: "{timestamp}: {content}".to_string(),
+            confirm_destructive: true,
+            empty_message: "No clips yet — copy something to get started. Press ? for help."
+                .to_string(),
+            save_mode: SaveMode::Immediate,
+            save_interval_secs: 30,
+            background_saves: false,
+            list_template: "{pin}[{time}] {label}{tag}{preview}{count}{source}".to_string(),
+            list_columns: false,
+            sort: SortOrder::Recent,
+            initial_selection: InitialSelection::Newest,
+            group_by_date: false,
+            quick_copy: false,
+            auto_pin_uses: 0,
+            log_usage: false,
+            notify_on_capture: false,
+            server_port: 0,
+            server_token: None,
+            read_only: false,
+        }
+    }
+}
+
+impl Config {
+    /// Reads `config.json`, falling back to the defaults when it is missing or unparsable.
+    pub fn load() -> Config {
+        Config::load_from(&data_dir().join("config.json"))
+    }
+
+    /// Like [`load`](Self::load), but from an explicit path. A path that exists but can't be
+    /// read (a directory, no permission) also gives the defaults, with a warning on stderr.
+    pub fn load_from(path: &Path) -> Config {
+        match fs::read_to_string(path) {
+            Ok(data) => serde_json::from_str(&data).unwrap_or_default(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Config::default(),
+            Err(e) => {
+                eprintln!(
+                    "clipb: can't read {} ({}), using the default settings",
+                    path.display(),
+                    e
+                );
+                Config::default()
+            }
+        }
+    }
+
+    /// Rejects settings that can't be used as given.
+    pub fn validate(&self) -> Result<(), String> {
+        // History files must stay inside the data directory.
+        if !is_plain_file_name(&self.history_filename) {
+            return Err(format!(
+                "history_filename must be a plain file name, got '{}'",
+                self.history_filename
+            ));
+        }
+        if let Some(name) = self.histories.iter().find(|name| !is_plain_file_name(name)) {
+            return Err(format!(
+                "histories must be plain names to put in file names, got '{}'",
+                name
+            ));
+        }
+        if self.server_port != 0 && self.server_token.as_deref().is_none_or(str::is_empty) {
+            return Err("server_port needs a server_token for clients to send".to_string());
+        }
+        for segment in template_segments(&self.list_template) {
+            if let Segment::Field(field) = segment
+                && !LIST_TEMPLATE_FIELDS.contains(&field)
+            {
+                return Err(format!(
+                    "list_template has unknown placeholder '{{{}}}' (expected one of {})",
+                    field,
+                    LIST_TEMPLATE_FIELDS.join(", ")
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// A text clip as it is stored: with `line_endings` and then `trim_policy` applied, unless
+    /// they are set to wait until the clip is copied back out.
+    pub fn captured_text<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        self.normalize(text, false)
+    }
+
+    /// Whether a text clip, as [`captured_text`](Self::captured_text) leaves it, is worth
+    /// storing: not empty by `skip_empty`, nor shorter than `min_entry_chars`.
+    pub fn keeps_text(&self, text: &str) -> bool {
+        !self.skip_empty.is_empty(text) && text.chars().count() >= self.min_entry_chars
+    }
+
+    /// The conversions [`captured_text`](Self::captured_text) leaves for copying, applied to
+    /// an entry's content.
+    pub fn copied_text<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        self.normalize(text, true)
+    }
+
+    // Applies the `line_endings` and `trim_policy` conversions that happen at capture (or, with
+    // `copying`, at copy) time, borrowing `text` when neither changes it.
+    fn normalize<'a>(&self, text: &'a str, copying: bool) -> Cow<'a, str> {
+        let mut text = Cow::Borrowed(text);
+        if self.line_endings_on_copy == copying {
+            text = and_then(text, |s| self.line_endings.apply(s));
+        }
+        if self.trim_on_copy == copying {
+            text = and_then(text, |s| self.trim_policy.apply(s));
+        }
+        text
+    }
+}
+
+// A name that can't climb out of, or into a subdirectory of, the directory it is joined to.
+fn is_plain_file_name(name: &str) -> bool {
+    !(name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']))
+}
+
+/// Which clips the poll thread treats as "empty" and refuses to store.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipEmpty {
+    /// Store everything, including the empty string.
+    Never,
+    /// Skip the empty string and clips made only of `\n`/`\r`. Spaces and tabs are kept,
+    /// so indentation-only fragments still get recorded.
+    Newlines,
+    /// Skip the empty string and any clip made only of whitespace.
+    Whitespace,
+}
+
+impl SkipEmpty {
+    pub fn is_empty(self, text: &str) -> bool {
+        match self {
+            SkipEmpty::Never => false,
+            SkipEmpty::Newlines => text.chars().all(|c| c == '\n' || c == '\r'),
+            SkipEmpty::Whitespace => text.chars().all(char::is_whitespace),
+        }
+    }
+}
+
+// Applies `f` to text that may already be a converted copy, borrowing for as long as possible.
+fn and_then<'a>(text: Cow<'a, str>, f: impl for<'b> Fn(&'b str) -> Cow<'b, str>) -> Cow<'a, str> {
+    match text {
+        Cow::Borrowed(s) => f(s),
+        Cow::Owned(s) => Cow::Owned(f(&s).into_owned()),
+    }
+}
+
+/// Line endings that `line_endings` converts text clips to.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LineEndings {
+    /// Keep whatever the clip came with.
+    Preserve,
+    /// `\n`, as Unix tools expect.
+    Lf,
+    /// `\r\n`, as Windows apps expect.
+    Crlf,
+}
+
+impl LineEndings {
+    /// Converts every line break in `text`, whether `\r\n`, `\n` or a lone `\r`, so mixed
+    /// endings all come out the same.
+    pub fn apply(self, text: &str) -> Cow<'_, str> {
+        let ending = match self {
+            LineEndings::Preserve => return Cow::Borrowed(text),
+            LineEndings::Lf => "\n",
+            LineEndings::Crlf => "\r\n",
+        };
+        let mut out = String::with_capacity(text.len());
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\r' => {
+                    chars.next_if_eq(&'\n');
+                    out.push_str(ending);
+                }
+                '\n' => out.push_str(ending),
+                _ => out.push(c),
+            }
+        }
+        if out == text {
+            Cow::Borrowed(text)
+        } else {
+            Cow::Owned(out)
+        }
+    }
+}
+
+/// What `trim_policy` removes from a text clip.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TrimPolicy {
+    /// Keep the clip exactly as copied.
+    None,
+    /// Whitespace, newlines included, at the end of the clip.
+    TrailingOnly,
+    /// Whitespace at the start and the end of the clip.
+    Both,
+    /// Whitespace at the end of each line and one final newline, for code: indentation is
+    /// never touched, and `\r\n` line endings stay.
+    Smart,
+}
+
+impl TrimPolicy {
+    pub fn apply(self, text: &str) -> Cow<'_, str> {
+        match self {
+            TrimPolicy::None => Cow::Borrowed(text),
+            TrimPolicy::TrailingOnly => Cow::Borrowed(text.trim_end()),
+            TrimPolicy::Both => Cow::Borrowed(text.trim()),
+            TrimPolicy::Smart => {
+                let mut out = String::with_capacity(text.len());
+                for line in text.split_inclusive('\n') {
+                    let body = line.trim_end_matches(['\r', '\n']);
+                    out.push_str(body.trim_end());
+                    out.push_str(&line[body.len()..]);
+                }
+                let end = out
+                    .strip_suffix("\r\n")
+                    .or_else(|| out.strip_suffix('\n'))
+                    .map(str::len);
+                if let Some(len) = end {
+                    out.truncate(len);
+                }
+                if out == text {
+                    Cow::Borrowed(text)
+                } else {
+                    Cow::Owned(out)
+                }
+            }
+        }
+    }
+}
+
+/// Orders the list can be shown in. Ties keep the newest first.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    /// Newest first.
+    Recent,
+    /// By content, ignoring case.
+    Alphabetical,
+    /// Most copied first; see [`Entry::use_count`](crate::Entry::use_count).
+    Usage,
+    /// Longest first.
+    Length,
+}
+
+/// Where the cursor starts when the list opens.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum InitialSelection {
+    /// The top of the list.
+    Newest,
+    /// The bottom of the list, for reading the history in the order it was copied.
+    Oldest,
+    /// The entry the cursor was on when clipb last exited, kept in `state.json`; the top of
+    /// the list when it is gone.
+    LastViewed,
+}
+
+/// The selections an entry can be restored to. Only Linux and the BSDs have a primary one.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RestoreTarget {
+    Clipboard,
+    Primary,
+    Both,
+}
+
+/// How a captured clip that is already pinned is treated.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PinnedMatch {
+    /// Like any other clip: folded into a recent match per `dedup_window`, else stored again.
+    Store,
+    /// Not stored; the pinned entry is left as it is.
+    Skip,
+    /// Not stored; the pinned entry moves to the top with the new timestamp.
+    Refresh,
+}
+
+/// How the HTML form of a clip (see [`Entry::html`](crate::Entry::html)) affects whether it
+/// matches an entry already stored, for `dedup_window`, `dedup` and `pinned_match`.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum HtmlMatch {
+    /// Only the plain text counts. The matched entry keeps its HTML, or takes the new clip's
+    /// if it had none.
+    Merge,
+    /// The HTML must be the same too, and text with HTML never matches text without it, so
+    /// each distinct form gets its own entry.
+    Distinct,
+}
+
+/// Which unpinned entry is dropped first when the history is full.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictionPolicy {
+    /// The one captured longest ago.
+    Fifo,
+    /// The one least recently captured or copied back out, so clips in regular use stay
+    /// however long ago they were first captured. Rewrites the history file on each eviction.
+    Lru,
+}
+
+/// When the poll thread's captures are written to disk. Edits made in the UI (pins, labels,
+/// deletions) are always saved straight away, taking any pending captures with them.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SaveMode {
+    /// Write each clip as it is captured.
+    Immediate,
+    /// Keep captures in memory and write them every `save_interval_secs` and on exit.
+    Interval,
+    /// Keep captures in memory until clipb exits.
+    OnExit,
+}
+
+/// Directory holding the config, history and snippet files. Not created by this call.
+pub fn data_dir() -> PathBuf {
+    // Tests keep their files out of the real data directory.
+    if cfg!(test) {
+        return std::env::temp_dir().join(format!("clipb-test-{}", std::process::id()));
+    }
+    let mut path = config_dir().unwrap_or_else(|| PathBuf::from("."));
+    path.push("clipman");
+    path
+}
+
+/// Path of `file_name` inside the data directory, creating the directory if needed. If that
+/// fails (say a file is in the way) it is logged, and writing to the returned path fails too.
+pub fn data_path(file_name: &str) -> PathBuf {
+    let path = data_dir();
+    if let Err(e) = fs::create_dir_all(&path) {
+        debug_log(&format!("can't create {}: {}", path.display(), e));
+    }
+    path.join(file_name)
+}
+
+/// Appends a timestamped line to `debug.log` in the data directory when `CLIPB_DEBUG` is set.
+pub fn debug_log(message: &str) {
+    if std::env::var_os("CLIPB_DEBUG").is_none() {
+        return;
+    }
+    if let Ok(mut file) = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(data_dir().join("debug.log"))
+    {
+        let _ = writeln!(file, "{} {}", chrono::Local::now().to_rfc3339(), message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_names_must_stay_in_the_data_directory() {
+        let with_name = |name: &str| Config {
+            histories: vec!["work".to_string(), name.to_string()],
+            ..Config::default()
+        };
+        assert!(with_name("notes.2024").validate().is_ok());
+        for name in ["", ".", "..", "../x", "/etc/x", "a/b", "a\\b"] {
+            let error = with_name(name).validate().unwrap_err();
+            assert!(error.starts_with("histories"), "{:?}: {}", name, error);
+        }
+        let config = Config {
+            history_filename: "../history.json".to_string(),
+            ..Config::default()
+        };
+        assert!(
+            config
+                .validate()
+                .unwrap_err()
+                .starts_with("history_filename")
+        );
+    }
+
+    #[test]
+    fn what_counts_as_empty() {
+        // Skipped under `Never`, `Newlines` and `Whitespace`.
+        let cases: &[(&str, [bool; 3])] = &[
+            ("", [false, true, true]),
+            ("\n", [false, true, true]),
+            ("\r\n\r", [false, true, true]),
+            ("    ", [false, false, true]),
+            ("\t\t", [false, false, true]),
+            ("  \n", [false, false, true]),
+            ("\u{a0}", [false, false, true]),
+            ("x", [false, false, false]),
+            ("    x\n", [false, false, false]),
+        ];
+        for (text, skipped) in cases {
+            let got = [SkipEmpty::Never, SkipEmpty::Newlines, SkipEmpty::Whitespace]
+                .map(|skip| skip.is_empty(text));
+            assert_eq!(&got, skipped, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn trim_policies_on_code() {
+        let code = "    fn main() {  \n        run();\t\n    }\n\n";
+        assert_eq!(TrimPolicy::None.apply(code), code);
+        assert_eq!(
+            TrimPolicy::TrailingOnly.apply(code),
+            "    fn main() {  \n        run();\t\n    }"
+        );
+        assert_eq!(
+            TrimPolicy::Both.apply(code),
+            "fn main() {  \n        run();\t\n    }"
+        );
+        // Only the final newline goes; the blank line before it is the clip's own.
+        assert_eq!(
+            TrimPolicy::Smart.apply(code),
+            "    fn main() {\n        run();\n    }\n"
+        );
+        assert_eq!(TrimPolicy::Smart.apply("  a \r\n  b\r\n"), "  a\r\n  b");
+        assert!(matches!(
+            TrimPolicy::Smart.apply("\n    indented"),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn trim_happens_at_capture_or_at_copy() {
+        let clip = "  x  \n";
+        let at_capture = Config {
+            trim_policy: TrimPolicy::Smart,
+            ..Config::default()
+        };
+        assert_eq!(at_capture.captured_text(clip), "  x");
+        assert_eq!(at_capture.copied_text(clip), clip);
+        let at_copy = Config {
+            trim_on_copy: true,
+            ..at_capture
+        };
+        assert_eq!(at_copy.captured_text(clip), clip);
+        assert_eq!(at_copy.copied_text(clip), "  x");
+    }
+
+    #[test]
+    fn line_endings_convert_lone_and_mixed_breaks() {
+        let mixed = "a\r\nb\nc\rd\r\n\r\n";
+        assert_eq!(LineEndings::Preserve.apply(mixed), mixed);
+        assert_eq!(LineEndings::Lf.apply(mixed), "a\nb\nc\nd\n\n");
+        assert_eq!(LineEndings::Crlf.apply(mixed), "a\r\nb\r\nc\r\nd\r\n\r\n");
+        // `\n\r` is two breaks, not a reversed `\r\n`.
+        assert_eq!(LineEndings::Lf.apply("x\n\ry"), "x\n\ny");
+        assert_eq!(LineEndings::Crlf.apply("\r"), "\r\n");
+        assert!(matches!(LineEndings::Lf.apply("a\nb"), Cow::Borrowed(_)));
+        assert!(matches!(
+            LineEndings::Crlf.apply("a\r\nb"),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn line_endings_are_converted_before_trimming() {
+        let config = Config {
+            line_endings: LineEndings::Lf,
+            trim_policy: TrimPolicy::Smart,
+            ..Config::default()
+        };
+        assert_eq!(config.captured_text("a \r\nb\r\n"), "a\nb");
+        let on_copy = Config {
+            line_endings_on_copy: true,
+            ..config
+        };
+        assert_eq!(on_copy.captured_text("a \r\nb\r\n"), "a\r\nb");
+        assert_eq!(on_copy.copied_text("a\r\nb"), "a\nb");
+    }
+}
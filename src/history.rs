@@ -0,0 +1,1519 @@
+use crate::clipboard::Selection;
+use crate::config::{
+    Config, EvictionPolicy, HtmlMatch, PinnedMatch, SaveMode, data_path, debug_log,
+};
+use crate::images;
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, SecondsFormat, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// One captured clip.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Entry {
+    /// Names the entry for the rest of the run, however it is bumped, edited or moved, so
+    /// changes made from a list drawn a moment ago still reach it. Handed out when the entry
+    /// is loaded or pushed, unique across histories; not stored.
+    #[serde(skip)]
+    pub id: u64,
+    /// When the clip was (last) captured; see [`now_timestamp`].
+    pub timestamp: String,
+    /// Position in capture order, set by [`ClipHistory::push`] and renewed when the entry is
+    /// moved to the top. Timestamps only have one-second resolution, so this is what orders
+    /// entries; those from before it was recorded have 0 and keep their file order.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub seq: u64,
+    /// The clip's text as captured: what the clipboard returned, after any of `line_endings`,
+    /// `trim_policy` and an `on_capture_replace` hook that are set (none are by default).
+    /// Nothing changes it once it is stored.
+    pub content: String,
+    /// Application that owned the focused window, when `track_source` is on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Short name shown ahead of the content in the list. Searched, but never copied.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Longer free-form note shown with the entry. Searched, but never copied.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// Times this content has been captured; entries from before dedup count once.
+    #[serde(default = "default_count")]
+    pub count: u32,
+    /// Times the entry has been copied back out of clipb.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub use_count: u32,
+    /// When the entry was last copied back out, in the same format as `timestamp`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_used: Option<String>,
+    /// Kept through pruning and clearing, and stored in the pinned file as well.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+    /// Delete the entry as soon as it is copied back out, for one-off secrets.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub burn: bool,
+    /// For image clips, the stored image's name (see [`crate::images`]); `content` then holds
+    /// a placeholder such as `[image 640×480]`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    /// Rich form of a text clip, when the source offered HTML alongside the plain text.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub html: Option<String>,
+    /// The selection the clip was captured from, recorded while `capture_primary` is on.
+    /// Untagged entries came from the clipboard.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_selection: Option<Selection>,
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+fn next_id() -> u64 {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+fn default_count() -> u32 {
+    1
+}
+
+fn is_zero<T: Default + PartialEq>(n: &T) -> bool {
+    *n == T::default()
+}
+
+impl Entry {
+    // Whether this entry stores the same clip as `clip`, be it text or an image. Under
+    // `HtmlMatch::Distinct` the HTML forms (or their absence) must match as well.
+    fn holds_clip(&self, clip: &Entry, html_match: HtmlMatch) -> bool {
+        self.content == clip.content
+            && self.image == clip.image
+            && (html_match == HtmlMatch::Merge || self.html == clip.html)
+    }
+
+    /// Case-insensitive substring match against the content, label and note.
+    pub fn matches(&self, query: &str) -> bool {
+        let query = Query {
+            text: query.to_lowercase(),
+            range: None,
+            selection: None,
+            options: SearchOptions::default(),
+        };
+        query.matches_text(self)
+    }
+}
+
+/// Switches that change how the text part of a [`Query`] matches.
+#[derive(Clone, Copy, Default)]
+pub struct SearchOptions {
+    /// Only match the query where it starts and ends at word boundaries.
+    pub whole_word: bool,
+    /// Treat any run of whitespace (spaces, tabs, newlines) as a single space, in both the
+    /// query and the entries, so multi-line clips can be found by their words alone.
+    pub collapse_whitespace: bool,
+}
+
+/// A search as typed: free text plus optional `@` date and selection filters.
+///
+/// `@today`, `@yesterday`, `@week` (last 7 days), `@month` (last 30 days) and `@YYYY-MM-DD`
+/// restrict matches to entries captured in that range; entries whose timestamp can't be parsed
+/// never match a date filter. `@primary` and `@clipboard` restrict them to entries captured
+/// from that selection. Other words, including unrecognised `@` words, are matched as text,
+/// case-insensitively.
+pub struct Query {
+    // Lowercased.
+    text: String,
+    range: Option<(DateTime<Local>, Option<DateTime<Local>>)>,
+    selection: Option<Selection>,
+    options: SearchOptions,
+}
+
+impl Query {
+    pub fn parse(query: &str, options: SearchOptions) -> Query {
+        let mut text = Vec::new();
+        let mut range = None;
+        let mut selection = None;
+        for word in query.split(' ') {
+            match word.strip_prefix('@') {
+                Some("primary") => selection = Some(Selection::Primary),
+                Some("clipboard") => selection = Some(Selection::Clipboard),
+                filter => match filter.and_then(date_range) {
+                    Some(r) => range = Some(r),
+                    None => text.push(word),
+                },
+            }
+        }
+        let mut text = text.join(" ").trim().to_lowercase();
+        if options.collapse_whitespace {
+            text = collapse_whitespace(&text);
+        }
+        Query {
+            text,
+            range,
+            selection,
+            options,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty() && self.range.is_none() && self.selection.is_none()
+    }
+
+    /// Index of the first line of `content` the text part of the query matches, for scrolling
+    /// a preview to it. A match spanning lines is placed on the line holding its first word.
+    pub fn match_line(&self, content: &str) -> Option<usize> {
+        if self.text.is_empty() {
+            return None;
+        }
+        let first_word = self.text.split(' ').next().unwrap_or(&self.text);
+        let line_with = |needle: &str| {
+            content
+                .lines()
+                .position(|line| line.to_lowercase().contains(needle))
+        };
+        line_with(&self.text).or_else(|| line_with(first_word))
+    }
+
+    /// Byte ranges of `text` the text part of the query matches, for highlighting. Matches are
+    /// found the way [`matches`](Self::matches) finds them, overlapping ones included, and
+    /// merged where they overlap or touch, so the ranges come out sorted and disjoint.
+    pub fn match_ranges(&self, text: &str) -> Vec<Range<usize>> {
+        if self.text.is_empty() {
+            return Vec::new();
+        }
+        // `text` folded like the haystack in `matches_text`, with the range of `text` each of
+        // its bytes came from.
+        let mut folded = String::new();
+        let mut origin: Vec<Range<usize>> = Vec::new();
+        let mut chars = text.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            let mut end = start + c.len_utf8();
+            if self.options.collapse_whitespace && c.is_whitespace() {
+                while let Some((i, c)) = chars.next_if(|(_, c)| c.is_whitespace()) {
+                    end = i + c.len_utf8();
+                }
+                folded.push(' ');
+                origin.push(start..end);
+                continue;
+            }
+            for lower in c.to_lowercase() {
+                folded.push(lower);
+                origin.extend(std::iter::repeat_n(start..end, lower.len_utf8()));
+            }
+        }
+
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        for (start, _) in folded.char_indices() {
+            let end = start + self.text.len();
+            if !folded[start..].starts_with(&self.text)
+                || self.options.whole_word && !at_word_boundaries(&folded, start, end)
+            {
+                continue;
+            }
+            let found = origin[start].start..origin[end - 1].end;
+            match ranges.last_mut() {
+                Some(last) if found.start <= last.end => last.end = last.end.max(found.end),
+                _ => ranges.push(found),
+            }
+        }
+        ranges
+    }
+
+    pub fn matches(&self, entry: &Entry) -> bool {
+        if let Some((start, end)) = self.range {
+            let Some(ts) = parse_timestamp(&entry.timestamp) else {
+                return false;
+            };
+            if ts < start || end.is_some_and(|end| ts >= end) {
+                return false;
+            }
+        }
+        if let Some(selection) = self.selection
+            && entry.source_selection.unwrap_or(Selection::Clipboard) != selection
+        {
+            return false;
+        }
+        self.matches_text(entry)
+    }
+
+    fn matches_text(&self, entry: &Entry) -> bool {
+        let found = |haystack: &str| {
+            let mut haystack = haystack.to_lowercase();
+            if self.options.collapse_whitespace {
+                haystack = collapse_whitespace(&haystack);
+            }
+            if self.options.whole_word {
+                contains_word(&haystack, &self.text)
+            } else {
+                haystack.contains(&self.text)
+            }
+        };
+        self.text.is_empty()
+            || found(&entry.content)
+            || entry.label.as_deref().is_some_and(found)
+            || entry.note.as_deref().is_some_and(found)
+    }
+}
+
+// Whether `needle` occurs in `haystack` with no word character directly before or after it.
+fn contains_word(haystack: &str, needle: &str) -> bool {
+    haystack
+        .match_indices(needle)
+        .any(|(start, found)| at_word_boundaries(haystack, start, start + found.len()))
+}
+
+// Whether `haystack[start..end]` has no word character directly before or after it.
+fn at_word_boundaries(haystack: &str, start: usize, end: usize) -> bool {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let before = haystack[..start].chars().next_back();
+    let after = haystack[end..].chars().next();
+    !before.is_some_and(is_word) && !after.is_some_and(is_word)
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// Start and (exclusive, open-ended if `None`) end of the range a date keyword names.
+fn date_range(keyword: &str) -> Option<(DateTime<Local>, Option<DateTime<Local>>)> {
+    let now = Local::now();
+    let day = |date: NaiveDate| {
+        date.and_hms_opt(0, 0, 0)?
+            .and_local_timezone(Local)
+            .earliest()
+    };
+    let day_range = |date: NaiveDate| Some((day(date)?, day(date.succ_opt()?)));
+    match keyword {
+        "today" => day_range(now.date_naive()),
+        "yesterday" => day_range(now.date_naive().pred_opt()?),
+        "week" => Some((now - TimeDelta::days(7), None)),
+        "month" => Some((now - TimeDelta::days(30), None)),
+        _ => day_range(NaiveDate::parse_from_str(keyword, "%Y-%m-%d").ok()?),
+    }
+}
+
+/// Format of the naive local timestamps written by older versions.
+pub const DISPLAY_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// New entries store RFC 3339 timestamps with an explicit offset so they are unambiguous across
+/// DST changes and machines; older entries keep their naive local-time strings.
+pub fn now_timestamp(utc: bool) -> String {
+    if utc {
+        Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+    } else {
+        Local::now().to_rfc3339_opts(SecondsFormat::Secs, false)
+    }
+}
+
+/// Parses both timestamp formats an [`Entry`] may carry.
+pub fn parse_timestamp(ts: &str) -> Option<DateTime<Local>> {
+    if let Ok(dt) = DateTime::parse_from_rfc3339(ts) {
+        return Some(dt.with_timezone(&Local));
+    }
+    NaiveDateTime::parse_from_str(ts, DISPLAY_TIME_FORMAT)
+        .ok()?
+        .and_local_timezone(Local)
+        .earliest()
+}
+
+// The history file is compacted once at least this many of its lines, and this share of them,
+// are dead.
+const COMPACT_MIN_DEAD: usize = 50;
+const COMPACT_DEAD_RATIO: f64 = 0.5;
+
+/// A history file and its entries, oldest first.
+///
+/// `None` is the default history; named histories (the `histories` config option) live in
+/// files of their own. Pinned entries are mirrored to a separate file so they survive the
+/// history file being cleared.
+pub struct ClipHistory {
+    file_name: String,
+    pinned_file_name: String,
+    // Files of the other histories, which share the images directory.
+    other_files: Vec<String>,
+    entries: Vec<Entry>,
+    // Lines in the file, including pruned entries not yet compacted away.
+    disk_lines: usize,
+    // Bumped on every write to the file, so a compaction can tell whether it is stale.
+    generation: u64,
+    // Id of the entry the user is looking at; never pruned.
+    protected: Option<u64>,
+    // Set from `save_mode`: captures only mark the history `dirty` until `flush`.
+    deferred: bool,
+    dirty: bool,
+    // Handed to the next entry pushed or moved to the top.
+    next_seq: u64,
+    // `max_file_bytes`, and the entries pruned to stay under it not yet reported.
+    max_file_bytes: usize,
+    capped: usize,
+    // Set from `read_only`: saves do nothing, so changes stay in memory.
+    read_only: bool,
+    eviction_policy: EvictionPolicy,
+    // Set from `background_saves`: file writes are queued for a thread of their own.
+    writer: Option<Writer>,
+    // Shared with the writer thread.
+    write_errors: Arc<WriteErrors>,
+}
+
+/// A rewrite of the history file with only its live entries, prepared so that the slow part
+/// runs without holding up other users of the [`ClipHistory`].
+pub struct Compaction {
+    path: PathBuf,
+    entries: Vec<Entry>,
+    generation: u64,
+}
+
+impl Compaction {
+    /// Writes the compacted file next to the real one. Needs no access to the history.
+    pub fn write(&self) -> io::Result<()> {
+        fs::write(self.temp_path(), jsonl(&self.entries))
+    }
+
+    fn temp_path(&self) -> PathBuf {
+        self.path.with_extension("compact")
+    }
+}
+
+impl ClipHistory {
+    /// Loads the newest `max_history` entries of the given history, plus any pins missing
+    /// from it. With `read_only` set, neither this nor anything later writes to its files.
+    pub fn load(config: &Config, name: Option<&str>) -> Self {
+        let file_name = history_file_name(&config.history_filename, name);
+        let pinned_file_name = pinned_file_name(&config.history_filename, name);
+        let other_files = config
+            .histories
+            .iter()
+            .map(|name| Some(name.as_str()))
+            .chain([None])
+            .flat_map(|name| {
+                let base = &config.history_filename;
+                [
+                    history_file_name(base, name),
+                    self::pinned_file_name(base, name),
+                ]
+            })
+            .filter(|other| *other != file_name && *other != pinned_file_name)
+            .collect();
+        let write_errors = Arc::default();
+        let (mut entries, disk_lines) = load_history(
+            &file_name,
+            config.max_history,
+            (!config.read_only).then_some(&write_errors),
+        );
+
+        // Pins that fell out of the history file come back as its oldest entries.
+        let missing: Vec<Entry> = load_pinned(&pinned_file_name)
+            .into_iter()
+            .filter(|p| {
+                !entries
+                    .iter()
+                    .any(|e| e.timestamp == p.timestamp && e.content == p.content)
+            })
+            .map(|p| Entry { pinned: true, ..p })
+            .collect();
+        let restore = !missing.is_empty();
+        entries.splice(0..0, missing);
+        // Stable, so entries without a sequence number stay in file order.
+        entries.sort_by_key(|e| e.seq);
+        for entry in &mut entries {
+            entry.id = next_id();
+        }
+        let next_seq = entries.iter().map(|e| e.seq).max().unwrap_or(0) + 1;
+
+        let mut hist = ClipHistory {
+            file_name,
+            pinned_file_name,
+            other_files,
+            disk_lines,
+            entries,
+            generation: 0,
+            protected: None,
+            deferred: config.save_mode != SaveMode::Immediate || config.read_only,
+            dirty: false,
+            next_seq,
+            max_file_bytes: config.max_file_bytes,
+            capped: 0,
+            read_only: config.read_only,
+            eviction_policy: config.eviction_policy,
+            writer: (config.background_saves && !config.read_only)
+                .then(|| Writer::start(Arc::clone(&write_errors))),
+            write_errors,
+        };
+        if restore {
+            hist.save();
+        }
+        hist
+    }
+
+    /// All entries, oldest first.
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Whether this history was loaded with `read_only`, so nothing it changes is saved.
+    pub fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// The history file, inside the data directory.
+    pub fn path(&self) -> PathBuf {
+        data_path(&self.file_name)
+    }
+
+    /// Rewrites the whole file; needed whenever an existing entry changes.
+    pub fn save(&mut self) {
+        if self.read_only {
+            return;
+        }
+        self.cap_file_size();
+        self.write_errors.missed.store(false, Ordering::Relaxed);
+        match &mut self.writer {
+            Some(writer) => writer.rewrite(data_path(&self.file_name), jsonl(&self.entries)),
+            None => {
+                let path = data_path(&self.file_name);
+                let written = try_write_atomic(&path, &jsonl(&self.entries));
+                report_write(&self.write_errors, &path, written);
+            }
+        }
+        self.disk_lines = self.entries.len();
+        self.generation += 1;
+        // A rewrite the writer thread had no room for is owed at the next flush.
+        self.dirty = self.writer.as_ref().is_some_and(|writer| writer.behind);
+    }
+
+    /// Blocks until every write queued under `background_saves` has reached the disk, such as
+    /// before exiting. Returns straight away otherwise.
+    pub fn wait_for_writes(&mut self) {
+        if let Some(writer) = &mut self.writer {
+            if writer.behind {
+                writer.send(Job::Replace {
+                    path: data_path(&self.file_name),
+                    data: jsonl(&self.entries),
+                });
+                writer.behind = false;
+            }
+            writer.wait();
+        }
+    }
+
+    /// Writes out captures held back by a deferred `save_mode`, if there are any.
+    pub fn flush(&mut self) {
+        if self.dirty || self.write_errors.missed.load(Ordering::Relaxed) {
+            self.save();
+        }
+    }
+
+    // Changes made by `push` and `bump` wait for `flush` under a deferred `save_mode`.
+    fn save_capture(&mut self) {
+        if self.deferred {
+            self.dirty = true;
+        } else {
+            self.save();
+        }
+    }
+
+    /// Whether enough dead lines (entries pruned from memory but still in the append-only file)
+    /// have piled up to be worth rewriting the file. Never under `background_saves`, where
+    /// captures queue the rewrite themselves.
+    pub fn needs_compaction(&self) -> bool {
+        self.writer.is_none() && self.dead_lines_piled_up()
+    }
+
+    fn dead_lines_piled_up(&self) -> bool {
+        // Deferred captures can leave more entries in memory than lines on disk.
+        let dead = self.disk_lines.saturating_sub(self.entries.len());
+        dead >= COMPACT_MIN_DEAD && dead as f64 >= self.disk_lines as f64 * COMPACT_DEAD_RATIO
+    }
+
+    /// Snapshots the live entries for a [`Compaction`]: call [`Compaction::write`] without
+    /// holding any lock on the history, then hand it to
+    /// [`finish_compaction`](Self::finish_compaction).
+    pub fn start_compaction(&self) -> Compaction {
+        Compaction {
+            path: self.path(),
+            entries: self.entries.clone(),
+            generation: self.generation,
+        }
+    }
+
+    /// Moves a written compaction over the history file, atomically. Returns `false`, leaving
+    /// the file alone, if the history has been written to since the snapshot was taken.
+    pub fn finish_compaction(&mut self, compaction: Compaction) -> bool {
+        let temp = compaction.temp_path();
+        if compaction.generation != self.generation || fs::rename(&temp, &compaction.path).is_err()
+        {
+            let _ = fs::remove_file(temp);
+            return false;
+        }
+        self.disk_lines = compaction.entries.len();
+        self.generation += 1;
+        true
+    }
+
+    /// Rewrites the pinned file from the entries currently pinned.
+    pub fn save_pinned(&self) {
+        if self.read_only {
+            return;
+        }
+        match &self.writer {
+            Some(writer) => writer.send(Job::Replace {
+                path: data_path(&self.pinned_file_name),
+                data: pinned_json(&self.entries),
+            }),
+            None => {
+                let path = data_path(&self.pinned_file_name);
+                let written = try_write_atomic(&path, &pinned_json(&self.entries));
+                report_write(&self.write_errors, &path, written);
+            }
+        }
+    }
+
+    /// The entry with the given [`id`](Entry::id), for editing in place. Call
+    /// [`save`](Self::save) afterwards.
+    pub fn find_mut(&mut self, id: u64) -> Option<&mut Entry> {
+        self.entries.iter_mut().find(|e| e.id == id)
+    }
+
+    fn position(&self, id: u64) -> Option<usize> {
+        self.entries.iter().position(|e| e.id == id)
+    }
+
+    /// Entries matching `query` (see [`Query`]), newest first.
+    pub fn search(&self, query: &str, options: SearchOptions) -> impl Iterator<Item = &Entry> {
+        let query = Query::parse(query, options);
+        self.entries.iter().rev().filter(move |e| query.matches(e))
+    }
+
+    /// Shields the entry with the given id from pruning, typically the one selected in a UI.
+    /// `None` lifts the protection.
+    pub fn protect(&mut self, id: Option<u64>) {
+        self.protected = id;
+    }
+
+    /// Adds a freshly captured clip. A clip matching one of the newest `dedup_window` entries
+    /// (any entry, with `dedup`) is moved to the top with the new timestamp and its count
+    /// bumped instead of being stored twice.
+    ///
+    /// `max_history` is a soft cap: going over it prunes the oldest entry that is neither
+    /// pinned nor [protected](Self::protect), and if every older entry is one of those the
+    /// history stays over the cap rather than losing it. `max_file_bytes` is enforced the same
+    /// way, on every push and save.
+    ///
+    /// A clip matching a pinned entry anywhere in the history is handled by `pinned_match`
+    /// first; with the default `store` it goes through the dedup check like any other clip.
+    /// Both checks go by the plain text unless `html_match` is `distinct`.
+    ///
+    /// Under a deferred `save_mode` the clip is only written by [`flush`](Self::flush) (or any
+    /// other save); the same goes for [`bump`](Self::bump).
+    pub fn push(&mut self, mut entry: Entry, config: &Config) {
+        // Pins are looked up across the whole history, ahead of the dedup window.
+        if config.pinned_match != PinnedMatch::Store
+            && let Some(pos) = self
+                .entries
+                .iter()
+                .position(|e| e.pinned && e.holds_clip(&entry, config.html_match))
+        {
+            if config.pinned_match == PinnedMatch::Refresh {
+                self.bump_at(pos, &entry);
+            }
+            return;
+        }
+        let window = if config.dedup {
+            self.entries.len()
+        } else {
+            config.dedup_window
+        };
+        if self.bump_within(&entry, window, config.html_match) {
+            return;
+        }
+
+        entry.seq = self.take_seq();
+        entry.id = next_id();
+        if self.deferred {
+            self.dirty = true;
+        } else {
+            match &mut self.writer {
+                Some(writer) => writer.append(data_path(&self.file_name), &entry),
+                None => {
+                    let path = data_path(&self.file_name);
+                    let written = try_append(&path, &jsonl(std::slice::from_ref(&entry)));
+                    report_write(&self.write_errors, &path, written);
+                }
+            }
+            self.disk_lines += 1;
+            self.generation += 1;
+        }
+        self.entries.push(entry);
+
+        // Pruning the oldest entry leaves it on disk as a dead line (load keeps only the newest
+        // `max_history`), until enough pile up for `needs_compaction`.
+        if self.entries.len() > config.max_history
+            && let Some(evicted) = self.next_evicted()
+        {
+            let entry = self.entries.remove(evicted);
+            self.release_image(&entry);
+            // Any other entry would be read back in, as it is newer than the pinned or protected
+            // ones kept ahead of it, so the file is rewritten without it. The same goes for
+            // whichever entry LRU picked.
+            if evicted > 0 || self.eviction_policy == EvictionPolicy::Lru {
+                self.save_capture();
+            }
+        }
+        // Going over `max_file_bytes` rewrites the file, so it shrinks straight away.
+        if self.cap_file_size() {
+            self.save_capture();
+        }
+        // The writer thread catches up, and compacts, by rewriting the whole file.
+        if let Some(writer) = &self.writer
+            && !self.deferred
+            && (writer.behind || self.dead_lines_piled_up())
+        {
+            self.save();
+        }
+    }
+
+    // The entry `eviction_policy` says goes next, among those neither pinned nor protected.
+    fn next_evicted(&self) -> Option<usize> {
+        let mut prunable = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| !e.pinned && self.protected != Some(e.id));
+        let (pos, _) = match self.eviction_policy {
+            EvictionPolicy::Fifo => prunable.next()?,
+            // Entries whose times can't be parsed count as the least recently used.
+            EvictionPolicy::Lru => prunable.min_by_key(|(_, e)| {
+                let captured = parse_timestamp(&e.timestamp);
+                let used = e.last_used.as_deref().and_then(parse_timestamp);
+                captured.max(used)
+            })?,
+        };
+        Some(pos)
+    }
+
+    // Prunes like `push` until the entries fit in `max_file_bytes` as written, counting what
+    // went in `capped`. Returns whether anything was pruned.
+    fn cap_file_size(&mut self) -> bool {
+        if self.max_file_bytes == 0 {
+            return false;
+        }
+        let line_len = |e: &Entry| serde_json::to_string(e).map_or(0, |line| line.len() + 1);
+        let mut total: usize = self.entries.iter().map(line_len).sum();
+        let mut removed = 0;
+        while total > self.max_file_bytes
+            && let Some(evicted) = self.next_evicted()
+        {
+            let entry = self.entries.remove(evicted);
+            total -= line_len(&entry);
+            self.release_image(&entry);
+            removed += 1;
+        }
+        self.capped += removed;
+        removed > 0
+    }
+
+    /// How many entries `max_file_bytes` has pruned since the last call, for warning about it.
+    pub fn take_capped(&mut self) -> usize {
+        std::mem::take(&mut self.capped)
+    }
+
+    /// Says why the last failed write to this history's files failed, if one has since this
+    /// was last called. What it would have written stays in memory, and the next
+    /// [`flush`](Self::flush) rewrites the file.
+    pub fn take_write_error(&mut self) -> Option<String> {
+        self.write_errors.message.lock().unwrap().take()
+    }
+
+    /// Moves the entry holding the same clip as `clip` (by its plain text) to the top with
+    /// `clip`'s timestamp and bumps its count. Returns `false` if there is no such entry.
+    pub fn bump(&mut self, clip: &Entry) -> bool {
+        self.bump_within(clip, self.entries.len(), HtmlMatch::Merge)
+    }
+
+    fn take_seq(&mut self) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        seq
+    }
+
+    // Like `bump`, but only looks at the newest `window` entries.
+    fn bump_within(&mut self, clip: &Entry, window: usize, html_match: HtmlMatch) -> bool {
+        let start = self.entries.len().saturating_sub(window);
+        let Some(pos) = self.entries[start..]
+            .iter()
+            .rposition(|e| e.holds_clip(clip, html_match))
+            .map(|pos| start + pos)
+        else {
+            return false;
+        };
+        self.bump_at(pos, clip);
+        true
+    }
+
+    fn bump_at(&mut self, pos: usize, clip: &Entry) {
+        let mut entry = self.entries.remove(pos);
+        entry.timestamp = clip.timestamp.clone();
+        entry.seq = self.take_seq();
+        entry.count = entry.count.saturating_add(1);
+        // The richer form wins: HTML already stored is kept, and a plain entry takes the
+        // clip's HTML if it brought some.
+        if entry.html.is_none() {
+            entry.html = clip.html.clone();
+        }
+        let pinned = entry.pinned;
+        self.entries.push(entry);
+        self.save_capture();
+        // Pins are matched to the history by their timestamp, so the pinned file follows it.
+        if pinned {
+            self.save_pinned();
+        }
+    }
+
+    /// Moves the entry with the given id to the top as if it had just been captured at `now`,
+    /// without counting a capture. Returns `false` if there is no such entry.
+    pub fn promote(&mut self, id: u64, now: String) -> bool {
+        let Some(pos) = self.position(id) else {
+            return false;
+        };
+        let mut entry = self.entries.remove(pos);
+        entry.timestamp = now;
+        entry.seq = self.take_seq();
+        let pinned = entry.pinned;
+        self.entries.push(entry);
+        self.save();
+        if pinned {
+            self.save_pinned();
+        }
+        true
+    }
+
+    /// Counts a copy, made at `now`, of the entry with the given id, pinning it once it has
+    /// been used `auto_pin_uses` times (never, if 0). Returns whether it was just pinned.
+    pub fn record_use(&mut self, id: u64, now: String, auto_pin_uses: u32) -> bool {
+        let Some(entry) = self.find_mut(id) else {
+            return false;
+        };
+        entry.use_count = entry.use_count.saturating_add(1);
+        entry.last_used = Some(now);
+        let pin = auto_pin_uses > 0 && !entry.pinned && entry.use_count >= auto_pin_uses;
+        entry.pinned |= pin;
+        self.save();
+        if pin {
+            self.save_pinned();
+        }
+        pin
+    }
+
+    /// Deletes the entry with the given id, pinned or not.
+    pub fn remove(&mut self, id: u64) -> bool {
+        let Some(pos) = self.position(id) else {
+            return false;
+        };
+        let entry = self.entries.remove(pos);
+        self.save();
+        if entry.pinned {
+            self.save_pinned();
+        }
+        self.release_image(&entry);
+        true
+    }
+
+    /// Deletes every entry that isn't pinned and returns how many went.
+    pub fn clear(&mut self) -> usize {
+        let removed = self.remove_where(|e| !e.pinned);
+        self.save();
+        removed
+    }
+
+    /// Drops entries captured before `cutoff` and returns how many went. Pinned entries and
+    /// entries whose timestamp can't be parsed are kept, since the latter's age is unknown.
+    pub fn prune_before(&mut self, cutoff: DateTime<Local>) -> usize {
+        let removed = self.remove_where(|e| {
+            !e.pinned && parse_timestamp(&e.timestamp).is_some_and(|ts| ts < cutoff)
+        });
+        if removed > 0 {
+            self.save();
+        }
+        removed
+    }
+
+    // Drops the entries `doomed` picks, and images only they used, without saving. Returns
+    // how many went.
+    fn remove_where(&mut self, doomed: impl Fn(&Entry) -> bool) -> usize {
+        let (removed, kept) = std::mem::take(&mut self.entries)
+            .into_iter()
+            .partition::<Vec<_>, _>(|e| doomed(e));
+        self.entries = kept;
+        for entry in &removed {
+            self.release_image(entry);
+        }
+        removed.len()
+    }
+
+    // Deletes the stored image of an entry that just went, unless something still refers to
+    // it: an entry left here, or a line in another history's files.
+    fn release_image(&self, gone: &Entry) {
+        let Some(name) = &gone.image else {
+            return;
+        };
+        if self.read_only || self.entries.iter().any(|e| e.image.as_ref() == Some(name)) {
+            return;
+        }
+        let field = format!("\"image\":{}", serde_json::to_string(name).unwrap());
+        let elsewhere = self.other_files.iter().any(|file| {
+            fs::read_to_string(data_path(file)).is_ok_and(|data| data.contains(&field))
+        });
+        if !elsewhere {
+            images::remove(name);
+        }
+    }
+}
+
+impl Drop for ClipHistory {
+    fn drop(&mut self) {
+        self.wait_for_writes();
+    }
+}
+
+// Named histories sit next to the configured file: `history.json` becomes `history-work.json`.
+fn history_file_name(base: &str, name: Option<&str>) -> String {
+    match name {
+        Some(name) => {
+            let (stem, ext) = split_extension(base);
+            format!("{}-{}{}", stem, name, ext)
+        }
+        None => base.to_string(),
+    }
+}
+
+// Pinned entries are also kept in their own file so clearing or corrupting the history
+// can't lose them. The default history keeps the original `pinned.json` names.
+fn pinned_file_name(base: &str, name: Option<&str>) -> String {
+    if base == "history.json" {
+        return history_file_name("pinned.json", name);
+    }
+    let (stem, _) = split_extension(base);
+    history_file_name(&format!("{}.pinned.json", stem), name)
+}
+
+fn split_extension(file_name: &str) -> (&str, &str) {
+    match file_name.rfind('.') {
+        Some(dot) if dot > 0 => file_name.split_at(dot),
+        _ => (file_name, ""),
+    }
+}
+
+fn load_pinned(file_name: &str) -> Vec<Entry> {
+    let data = fs::read_to_string(data_path(file_name)).unwrap_or_default();
+    serde_json::from_str(&data).unwrap_or_default()
+}
+
+fn pinned_json(entries: &[Entry]) -> String {
+    let pinned: Vec<&Entry> = entries.iter().filter(|e| e.pinned).collect();
+    // Machine-managed like the history file, so compact; pretty files from older versions
+    // still load.
+    serde_json::to_string(&pinned).unwrap()
+}
+
+// History is stored as JSON Lines, one entry per line, so it can be streamed in and only the
+// newest `max_history` entries are ever held in memory. Returns them with the number of lines
+// in the file, which entries past `max_history` (and lines that don't parse) stay in until a
+// save or compaction. Given `rewrite`, files in the legacy format are converted, and a failure
+// to is kept there.
+fn load_history(
+    file_name: &str,
+    max_history: usize,
+    rewrite: Option<&WriteErrors>,
+) -> (Vec<Entry>, usize) {
+    let path = data_path(file_name);
+    let Ok(file) = fs::File::open(&path) else {
+        return (Vec::new(), 0);
+    };
+    let mut reader = BufReader::new(file);
+
+    // Older versions wrote a single pretty-printed JSON array; convert it in place.
+    let is_legacy = reader
+        .fill_buf()
+        .map(|buf| buf.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'['))
+        .unwrap_or(false);
+    if is_legacy {
+        let mut hist: Vec<Entry> = serde_json::from_reader(reader).unwrap_or_default();
+        // Converted whole: `max_history` limits what is loaded, not what the file keeps.
+        if let Some(write_errors) = rewrite {
+            let written = try_write_atomic(&path, &jsonl(&hist));
+            report_write(write_errors, &path, written);
+        }
+        let lines = hist.len();
+        if hist.len() > max_history {
+            hist.drain(..hist.len() - max_history);
+        }
+        return (hist, lines);
+    }
+
+    let mut hist = VecDeque::new();
+    let mut lines = 0;
+    for line in reader.lines().map_while(Result::ok) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        lines += 1;
+        if let Ok(entry) = serde_json::from_str::<Entry>(&line) {
+            hist.push_back(entry);
+            if hist.len() > max_history {
+                hist.pop_front();
+            }
+        }
+    }
+
+    (hist.into(), lines)
+}
+
+fn jsonl(entries: &[Entry]) -> String {
+    let mut data = String::new();
+    for entry in entries {
+        data.push_str(&serde_json::to_string(entry).unwrap());
+        data.push('\n');
+    }
+    data
+}
+
+// Written to a temporary file and renamed into place, so a crash mid-write can't truncate it.
+fn try_write_atomic(path: &Path, data: &str) -> io::Result<()> {
+    let temp = path.with_extension("tmp");
+    fs::write(&temp, data)?;
+    fs::rename(&temp, path)
+}
+
+fn try_append(path: &Path, data: &str) -> io::Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    file.write_all(data.as_bytes())
+}
+
+// Writes that failed: the last one's message, until `take_write_error`, and whether the file
+// has missed any since it was last rewritten whole.
+#[derive(Default)]
+struct WriteErrors {
+    message: Mutex<Option<String>>,
+    missed: AtomicBool,
+}
+
+// A failed write is logged and kept for `take_write_error`, so the user hears of it.
+fn report_write(errors: &WriteErrors, path: &Path, written: io::Result<()>) {
+    if let Err(e) = written {
+        let message = format!("Couldn't write {}: {}", path.display(), e);
+        debug_log(&message);
+        *errors.message.lock().unwrap() = Some(message);
+        errors.missed.store(true, Ordering::Relaxed);
+    }
+}
+
+// Writes queued for the writer thread can pile up this far before captures stop queueing
+// appends and the next save sends the whole file instead.
+const WRITE_QUEUE: usize = 64;
+
+enum Job {
+    Append { path: PathBuf, data: String },
+    Replace { path: PathBuf, data: String },
+    // Answered once everything queued before it is written.
+    Wait(mpsc::Sender<()>),
+}
+
+// The thread that does a history's file writes under `background_saves`, in the order they
+// were queued. Dropping it waits for the queue to drain.
+struct Writer {
+    jobs: Option<SyncSender<Job>>,
+    thread: Option<JoinHandle<()>>,
+    // A history write was dropped because the queue was full; the next one rewrites the file.
+    behind: bool,
+}
+
+impl Writer {
+    fn start(write_errors: Arc<WriteErrors>) -> Self {
+        let (jobs, queue) = mpsc::sync_channel(WRITE_QUEUE);
+        Writer {
+            jobs: Some(jobs),
+            thread: Some(thread::spawn(move || write_jobs(queue, &write_errors))),
+            behind: false,
+        }
+    }
+
+    // Queues `job` even if that means waiting for room; for writes that can't be redone later.
+    fn send(&self, job: Job) {
+        if let Some(jobs) = &self.jobs {
+            let _ = jobs.send(job);
+        }
+    }
+
+    // Queues a history write without waiting. A full queue means the disk can't keep up:
+    // the write is dropped and the file is rewritten whole once there is room again.
+    fn try_send(&mut self, job: Job) {
+        let Some(jobs) = &self.jobs else {
+            return;
+        };
+        match jobs.try_send(job) {
+            Ok(()) => self.behind = false,
+            Err(TrySendError::Full(_)) => {
+                if !self.behind {
+                    debug_log("history writes are falling behind; the next save rewrites the file");
+                }
+                self.behind = true;
+            }
+            Err(TrySendError::Disconnected(_)) => {}
+        }
+    }
+
+    fn append(&mut self, path: PathBuf, entry: &Entry) {
+        // Appending now would leave out the dropped writes; the caller's next save covers it.
+        if !self.behind {
+            let data = jsonl(std::slice::from_ref(entry));
+            self.try_send(Job::Append { path, data });
+        }
+    }
+
+    fn rewrite(&mut self, path: PathBuf, data: String) {
+        self.try_send(Job::Replace { path, data });
+    }
+
+    fn wait(&self) {
+        let (done, finished) = mpsc::channel();
+        self.send(Job::Wait(done));
+        let _ = finished.recv();
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        self.jobs = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn write_jobs(queue: Receiver<Job>, write_errors: &WriteErrors) {
+    while let Ok(job) = queue.recv() {
+        let mut batch = vec![job];
+        batch.extend(queue.try_iter());
+        // A rewrite makes the writes to the same file queued before it pointless, back to the
+        // last `Wait`, which was promised those writes first.
+        let mut skip = vec![false; batch.len()];
+        for (i, job) in batch.iter().enumerate() {
+            let Job::Replace { path, .. } = job else {
+                continue;
+            };
+            for (j, earlier) in batch[..i].iter().enumerate().rev() {
+                match earlier {
+                    Job::Wait(_) => break,
+                    Job::Append { path: other, .. } | Job::Replace { path: other, .. }
+                        if other == path =>
+                    {
+                        skip[j] = true;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for (job, skip) in batch.into_iter().zip(skip) {
+            if skip {
+                continue;
+            }
+            let (path, written) = match job {
+                Job::Append { path, data } => {
+                    let written = try_append(&path, &data);
+                    (path, written)
+                }
+                Job::Replace { path, data } => {
+                    let written = try_write_atomic(&path, &data);
+                    (path, written)
+                }
+                Job::Wait(done) => {
+                    let _ = done.send(());
+                    continue;
+                }
+            };
+            report_write(write_errors, &path, written);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clipboard::ClipboardBackend;
+    use std::error::Error;
+
+    // A config whose history files, named after the test, start out empty.
+    fn fresh_config(name: &str) -> Config {
+        let config = Config {
+            history_filename: format!("{}.json", name),
+            ..Config::default()
+        };
+        let _ = fs::remove_file(data_path(&config.history_filename));
+        let _ = fs::remove_file(data_path(&pinned_file_name(&config.history_filename, None)));
+        config
+    }
+
+    fn clip(content: &str, second: u32) -> Entry {
+        Entry {
+            timestamp: format!("2024-03-15T14:00:{:02}+00:00", second),
+            content: content.to_string(),
+            count: 1,
+            ..Default::default()
+        }
+    }
+
+    fn contents(hist: &ClipHistory) -> Vec<(&str, bool)> {
+        hist.entries()
+            .iter()
+            .map(|e| (e.content.as_str(), e.pinned))
+            .collect()
+    }
+
+    fn pin(hist: &mut ClipHistory, content: &str) {
+        let id = hist
+            .entries()
+            .iter()
+            .find(|e| e.content == content)
+            .unwrap()
+            .id;
+        hist.find_mut(id).unwrap().pinned = true;
+        hist.save();
+        hist.save_pinned();
+    }
+
+    // Hands back exactly what was last set, as a clipboard should.
+    #[derive(Default)]
+    struct MockClipboard {
+        text: String,
+    }
+
+    impl ClipboardBackend for MockClipboard {
+        fn get_text(&mut self) -> Result<String, Box<dyn Error + Send + Sync>> {
+            Ok(self.text.clone())
+        }
+
+        fn set_text(&mut self, text: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+            self.text = text.to_string();
+            Ok(())
+        }
+    }
+
+    // Pseudo-random clips built from the pieces most likely to be mangled on the way: every kind
+    // of whitespace and line ending, escapes, control characters and multi-byte text.
+    fn sample_clips(count: usize) -> Vec<String> {
+        const PIECES: &[&str] = &[
+            "",
+            " ",
+            "   ",
+            "\t",
+            "\n",
+            "\n\n",
+            "\r\n",
+            "\r",
+            "\u{a0}",
+            "\u{200b}",
+            "\u{feff}",
+            "\0",
+            "\u{1b}[31m",
+            "a",
+            "word",
+            "é",
+            "日本語",
+            "🦀",
+            "\"",
+            "\\",
+            "\\n",
+            "{}",
+            "[",
+        ];
+        // xorshift64, seeded so a failure can be reproduced.
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as usize
+        };
+        (0..count)
+            .map(|_| {
+                let pieces = next() % 16;
+                (0..pieces).map(|_| PIECES[next() % PIECES.len()]).collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn clips_round_trip_byte_for_byte() {
+        let config = Config {
+            max_history: 1000,
+            dedup_window: 0,
+            ..fresh_config("round-trip")
+        };
+        let samples = sample_clips(500);
+        let mut clipboard = MockClipboard::default();
+        let mut hist = ClipHistory::load(&config, None);
+        for sample in &samples {
+            clipboard.set_text(sample).unwrap();
+            let text = clipboard.get_text().unwrap();
+            let entry = Entry {
+                timestamp: now_timestamp(true),
+                content: config.captured_text(&text).into_owned(),
+                count: 1,
+                ..Default::default()
+            };
+            hist.push(entry, &config);
+        }
+        drop(hist);
+
+        let hist = ClipHistory::load(&config, None);
+        assert_eq!(hist.entries().len(), samples.len());
+        for (entry, sample) in hist.entries().iter().zip(&samples) {
+            clipboard
+                .set_text(&config.copied_text(&entry.content))
+                .unwrap();
+            assert_eq!(clipboard.get_text().unwrap().as_bytes(), sample.as_bytes());
+        }
+    }
+
+    #[test]
+    fn entries_pruned_past_a_pin_stay_pruned() {
+        let config = Config {
+            max_history: 2,
+            ..fresh_config("pruned-past-pin")
+        };
+        let mut hist = ClipHistory::load(&config, None);
+        hist.push(clip("P", 0), &config);
+        pin(&mut hist, "P");
+        hist.push(clip("A", 1), &config);
+        hist.push(clip("B", 2), &config);
+        assert_eq!(contents(&hist), [("P", true), ("B", false)]);
+        drop(hist);
+
+        let hist = ClipHistory::load(&config, None);
+        assert_eq!(contents(&hist), [("P", true), ("B", false)]);
+    }
+
+    #[test]
+    fn entries_pruned_past_the_selection_stay_pruned() {
+        let config = Config {
+            max_history: 2,
+            ..fresh_config("pruned-past-selection")
+        };
+        let mut hist = ClipHistory::load(&config, None);
+        hist.push(clip("S", 0), &config);
+        hist.protect(Some(hist.entries()[0].id));
+        hist.push(clip("A", 1), &config);
+        hist.push(clip("B", 2), &config);
+        assert_eq!(contents(&hist), [("S", false), ("B", false)]);
+        drop(hist);
+
+        let hist = ClipHistory::load(&config, None);
+        assert_eq!(contents(&hist), [("S", false), ("B", false)]);
+    }
+
+    #[test]
+    fn a_smaller_max_history_loads_less_and_keeps_the_file() {
+        let config = Config {
+            max_history: 10,
+            dedup_window: 0,
+            ..fresh_config("smaller-max")
+        };
+        let mut hist = ClipHistory::load(&config, None);
+        for second in 0..10 {
+            hist.push(clip(&second.to_string(), second), &config);
+        }
+        drop(hist);
+
+        let small = Config {
+            max_history: 3,
+            ..config.clone()
+        };
+        let hist = ClipHistory::load(&small, None);
+        assert_eq!(contents(&hist), [("7", false), ("8", false), ("9", false)]);
+        drop(hist);
+        let hist = ClipHistory::load(&config, None);
+        assert_eq!(hist.entries().len(), 10);
+    }
+
+    #[test]
+    fn images_go_with_the_last_entry_showing_them() {
+        let config = Config {
+            dedup_window: 0,
+            ..fresh_config("released-images")
+        };
+        let image = crate::Image {
+            width: 1,
+            height: 1,
+            rgba: vec![1, 2, 3, 4],
+        };
+        let name = images::store(&image).unwrap();
+        let stored = || images::load(&name).is_some();
+        let mut hist = ClipHistory::load(&config, None);
+        for (content, second) in [("[image 1×1]", 0), ("[image 1×1] again", 1)] {
+            let entry = Entry {
+                image: Some(name.clone()),
+                ..clip(content, second)
+            };
+            hist.push(entry, &config);
+        }
+        hist.push(clip("text", 2), &config);
+
+        let first = hist.entries()[0].id;
+        hist.remove(first);
+        assert!(stored());
+        assert_eq!(hist.clear(), 2);
+        assert!(!stored());
+    }
+
+    #[test]
+    fn failed_writes_are_reported_and_made_up_by_flush() {
+        let config = fresh_config("failed-writes");
+        let path = data_path(&config.history_filename);
+        let mut hist = ClipHistory::load(&config, None);
+        // A directory where the file should be makes every append fail.
+        fs::create_dir(&path).unwrap();
+        hist.push(clip("kept", 0), &config);
+        let error = hist.take_write_error().unwrap();
+        assert!(error.contains("failed-writes.json"), "{}", error);
+        assert_eq!(hist.take_write_error(), None);
+
+        fs::remove_dir(&path).unwrap();
+        hist.flush();
+        assert_eq!(hist.take_write_error(), None);
+        let reloaded = ClipHistory::load(&config, None);
+        assert_eq!(contents(&reloaded), [("kept", false)]);
+    }
+
+    #[test]
+    fn captures_between_frames_do_not_redirect_edits() {
+        let config = Config {
+            dedup: true,
+            ..fresh_config("stable-ids")
+        };
+        let history = Arc::new(Mutex::new(ClipHistory::load(&config, None)));
+        for (second, content) in ["a", "b", "c"].into_iter().enumerate() {
+            history
+                .lock()
+                .unwrap()
+                .push(clip(content, second as u32), &config);
+        }
+        // What the UI drew, and the entries picked from it.
+        let drawn = history.lock().unwrap().entries().to_vec();
+        let id_of = |content: &str| drawn.iter().find(|e| e.content == content).unwrap().id;
+        let (doomed, pinned) = (id_of("b"), id_of("c"));
+
+        // Captures land before either key is acted on: a new clip, and one that moves "a" up.
+        let poller = {
+            let history = Arc::clone(&history);
+            let config = config.clone();
+            thread::spawn(move || {
+                let mut hist = history.lock().unwrap();
+                hist.push(clip("d", 3), &config);
+                hist.push(clip("a", 4), &config);
+            })
+        };
+        poller.join().unwrap();
+
+        let mut hist = history.lock().unwrap();
+        assert!(hist.remove(doomed));
+        hist.find_mut(pinned).unwrap().pinned = true;
+        hist.save();
+        assert_eq!(contents(&hist), [("c", true), ("d", false), ("a", false)]);
+        drop(hist);
+        let reloaded = ClipHistory::load(&config, None);
+        assert_eq!(
+            contents(&reloaded),
+            [("c", true), ("d", false), ("a", false)]
+        );
+    }
+
+    #[test]
+    fn html_match_decides_whether_forms_merge() {
+        let rich = |html: Option<&str>, second| Entry {
+            html: html.map(str::to_string),
+            ..clip("x", second)
+        };
+        let clips = [
+            rich(None, 0),
+            rich(Some("<b>x</b>"), 1),
+            rich(Some("<i>x</i>"), 2),
+            rich(None, 3),
+        ];
+        let forms = |hist: &ClipHistory| -> Vec<(Option<String>, u32)> {
+            hist.entries()
+                .iter()
+                .map(|e| (e.html.clone(), e.count))
+                .collect()
+        };
+
+        // One entry, keeping the first HTML it was given.
+        let merge = Config {
+            dedup: true,
+            html_match: HtmlMatch::Merge,
+            ..fresh_config("html-merge")
+        };
+        let mut hist = ClipHistory::load(&merge, None);
+        for clip in clips.clone() {
+            hist.push(clip, &merge);
+        }
+        assert_eq!(forms(&hist), [(Some("<b>x</b>".to_string()), 4)]);
+
+        // One entry per form, plain text included.
+        let distinct = Config {
+            dedup: true,
+            html_match: HtmlMatch::Distinct,
+            ..fresh_config("html-distinct")
+        };
+        let mut hist = ClipHistory::load(&distinct, None);
+        for clip in clips {
+            hist.push(clip, &distinct);
+        }
+        assert_eq!(
+            forms(&hist),
+            [
+                (Some("<b>x</b>".to_string()), 1),
+                (Some("<i>x</i>".to_string()), 1),
+                (None, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn moving_a_pin_to_the_top_does_not_restore_it_twice() {
+        let config = fresh_config("promoted-pin");
+        let mut hist = ClipHistory::load(&config, None);
+        hist.push(clip("secret", 0), &config);
+        pin(&mut hist, "secret");
+        hist.push(clip("other", 1), &config);
+        let id = hist.entries()[0].id;
+        hist.promote(id, "2024-03-15T14:00:02+00:00".to_string());
+        assert_eq!(contents(&hist), [("other", false), ("secret", true)]);
+        drop(hist);
+
+        let hist = ClipHistory::load(&config, None);
+        assert_eq!(contents(&hist), [("other", false), ("secret", true)]);
+    }
+
+    #[test]
+    fn recapturing_a_pin_does_not_restore_it_twice() {
+        let config = Config {
+            dedup: true,
+            ..fresh_config("bumped-pin")
+        };
+        let mut hist = ClipHistory::load(&config, None);
+        hist.push(clip("secret", 0), &config);
+        pin(&mut hist, "secret");
+        hist.push(clip("other", 1), &config);
+        hist.push(clip("secret", 2), &config);
+        drop(hist);
+
+        let hist = ClipHistory::load(&config, None);
+        assert_eq!(contents(&hist), [("other", false), ("secret", true)]);
+    }
+}
@@ -0,0 +1,79 @@
+use crate::clipboard::Image;
+use crate::config::{data_dir, debug_log};
+use crate::transform::fnv1a;
+use image::{ExtendedColorType, RgbaImage};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+// Image clips are stored as PNG files next to the history, which only records their names.
+fn image_path(name: &str) -> PathBuf {
+    let mut path = data_dir();
+    path.push("images");
+    // As with `data_path`, a failure shows up when the file itself is written or read.
+    let _ = fs::create_dir_all(&path);
+    path.push(name);
+    path
+}
+
+/// File name an image is stored under. Derived from the size and pixels with a fixed hash
+/// ([`fnv1a`]), so copying the same image again maps to the same file in every run.
+pub fn image_name(image: &Image) -> String {
+    format!(
+        "{}x{}-{:016x}.png",
+        image.width,
+        image.height,
+        fnv1a(&image.rgba)
+    )
+}
+
+/// Writes `image` as PNG unless it is already stored, and returns its name.
+pub fn store(image: &Image) -> Result<String, image::ImageError> {
+    let name = image_name(image);
+    let path = image_path(&name);
+    if !path.exists() {
+        image::save_buffer(
+            &path,
+            &image.rgba,
+            image.width as u32,
+            image.height as u32,
+            ExtendedColorType::Rgba8,
+        )?;
+    }
+    Ok(name)
+}
+
+/// Deletes a stored image, for when the last entry showing it goes.
+pub fn remove(name: &str) {
+    match fs::remove_file(image_path(name)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            debug_log(&format!("image {} not deleted: {}", name, e));
+        }
+        _ => {}
+    }
+}
+
+/// Reads a stored image back, e.g. to restore it to the clipboard.
+pub fn load(name: &str) -> Option<Image> {
+    let decoded = image::open(image_path(name)).ok()?.into_rgba8();
+    Some(to_image(decoded))
+}
+
+/// A stored image scaled down, keeping its aspect ratio, to fit `max_width` × `max_height`.
+pub fn thumbnail(name: &str, max_width: u32, max_height: u32) -> Option<Image> {
+    let decoded = image::open(image_path(name)).ok()?;
+    let scaled = if decoded.width() > max_width || decoded.height() > max_height {
+        decoded.thumbnail(max_width, max_height)
+    } else {
+        decoded
+    };
+    Some(to_image(scaled.into_rgba8()))
+}
+
+fn to_image(buffer: RgbaImage) -> Image {
+    Image {
+        width: buffer.width() as usize,
+        height: buffer.height() as usize,
+        rgba: buffer.into_raw(),
+    }
+}
@@ -0,0 +1,32 @@
+//! The history engine behind the `clipb` clipboard manager.
+//!
+//! [`ClipHistory`] owns one history file: loading, appending captured clips, pruning and
+//! searching. [`capture::run`] watches any [`ClipboardBackend`] and feeds what it sees into a
+//! `ClipHistory`; the `clipb` binary is a terminal UI on top of the two.
+
+/// Watching the clipboard and storing what is copied.
+pub mod capture;
+/// Where clips come from and go back to.
+pub mod clipboard;
+/// The user's `config.json` and the data directory it lives in.
+pub mod config;
+/// Captured entries and the files they are stored in.
+pub mod history;
+/// Storage for image clips.
+pub mod images;
+/// Running the user's external commands.
+pub mod process;
+/// The opt-in local HTTP API.
+pub mod server;
+/// Decoders and other transforms of clip contents.
+pub mod transform;
+/// The opt-in log of entries copied back out.
+pub mod usage;
+
+pub use capture::Capture;
+pub use clipboard::{ClipboardBackend, Image, Selection, SystemClipboard};
+pub use config::{
+    Config, EvictionPolicy, HtmlMatch, InitialSelection, LineEndings, PinnedMatch, RestoreTarget,
+    SaveMode, SkipEmpty, SortOrder, TrimPolicy,
+};
+pub use history::{ClipHistory, Entry};
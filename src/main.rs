@@ -1,288 +1,2932 @@
-use arboard::Clipboard;
-use chrono::Local;
+use chrono::{DateTime, Local, TimeDelta};
+use clipb::capture::{self, Capture, Restored};
+use clipb::config::{data_dir, data_path};
+use clipb::history::{DISPLAY_TIME_FORMAT, Query, SearchOptions, now_timestamp, parse_timestamp};
+use clipb::process::{run_with_timeout, shell_command};
+use clipb::transform::{
+    Segment, decode_base64, decode_hex, encode_base64, fill_template, hex_dump, markdown_fence,
+    shell_quote, similarity, strip_ansi, template_segments,
+};
+use clipb::{
+    ClipHistory, ClipboardBackend, Config, Entry, InitialSelection, RestoreTarget, Selection,
+    SortOrder, SystemClipboard,
+};
+use clipb::{Image, images, server, usage};
 use crossterm::{
-    event::{self, Event as CEvent, KeyCode},
+    cursor::MoveTo,
+    event::{self, Event as CEvent, KeyCode, KeyModifiers},
     execute,
     terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode},
 };
-use dirs::config_dir;
 use ratatui::{
-    Terminal,
+    Terminal, TerminalOptions, Viewport,
     backend::CrosstermBackend,
-    widgets::{Block, Borders, List, ListItem, ListState},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph, Wrap},
 };
 use serde::{Deserialize, Serialize};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::error::Error;
 use std::fs;
-use std::io::{self, Write};
-use std::path::PathBuf;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
 use std::sync::{Arc, Mutex};
+use std::thread;
 use std::thread::spawn;
-use std::{thread, time::Duration};
+use std::time::{Duration, Instant};
+
+#[derive(Default)]
+struct CliArgs {
+    command: Option<String>,
+    poll_interval_ms: Option<u64>,
+    // Limits what this run lists; unlike `max_history` in the config, nothing is dropped.
+    max_entries: Option<usize>,
+    older_than: Option<TimeDelta>,
+    config_path: Option<PathBuf>,
+    read_only: bool,
+    // Opens the history already searching for this.
+    search: Option<String>,
+    // Arguments after the command, for `search` and `get`.
+    operands: Vec<String>,
+    search_options: SearchOptions,
+    help: bool,
+    version: bool,
+}
+
+const USAGE: &str = "\
+clipb — a terminal clipboard history
+
+Usage: clipb [command] [options]
 
+Commands:
+  (none)      watch the clipboard and browse the history
+  pick        choose an entry and print it to stdout; exits 1 if cancelled
+  view        browse the history read-only (same as --read-only)
+  prune       delete entries older than --older-than
+  search <query>  print matching entries as '<n><TAB><first line>', newest first; exits 1
+              if nothing matches (same syntax as / in the history, @ filters included)
+  get <n>     print entry n (1 is the newest, as numbered by search)
+  help        show this message
+
+Options:
+  --poll <ms>             clipboard poll interval
+  --max <entries>         list only the newest <entries> (and pins) this run
+  --config <file>         read this config file instead of config.json
+  --read-only             don't capture clips or change the history
+  --search <query>        open the history already searching for <query>
+  --older-than <duration> with prune: cutoff such as 90s, 30m, 12h, 3d or 2w
+  --word                  with search: match whole words only
+  --any-spacing           with search: treat any run of whitespace as one space
+  -h, --help              show this message
+  -V, --version           show the version
+
+Keys (press ? in the history for the full list):
+  Enter copy  / search  v view  p pin  l label  x delete  s snippets  q quit
+
+Config, history and snippets live in the clipman directory of the platform config dir.
+";
+
+// Reusable text kept in `snippets.json`, listed separately from captured history and never pruned.
 #[derive(Serialize, Deserialize, Clone)]
-struct Entry {
-    timestamp: String,
+struct Snippet {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
     content: String,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
-struct Config {
-    max_history: usize,
-    poll_interval_ms: u64,
+// A snippet being written in the snippets list: a new one without an `index`.
+struct SnippetDraft {
+    index: Option<usize>,
+    name: String,
+    content: String,
+}
+
+// What the snippets list is doing besides browsing.
+enum SnippetEdit {
+    // Typing the draft's name; Enter moves on to its content.
+    Naming(SnippetDraft),
+    // Typing its content, where Ctrl+J starts a new line.
+    Writing(SnippetDraft),
+    // Waiting for y/n to delete the snippet at `index`, with `confirm_destructive`.
+    Deleting { index: usize },
+}
+
+// Full-screen, scrollable view of one piece of text, drawn over whatever mode is active.
+struct View {
+    title: String,
+    content: String,
+    scroll: usize,
+}
+
+const HELP: &str = "\
+History
+  ↑/↓         move the selection
+  Enter       copy the entry back to the clipboard
+  1-9         copy one of the top nine entries (with quick_copy)
+  c           copy as plain text, without HTML or ANSI colors
+  o / O       open a file path entry / its containing folder
+  r           copy a reference (reference_template) instead of the content
+  [ / ]       copy just the first / last line
+  Q           copy quoted: s shell-quoted, m as a Markdown code block
+  P           copy and paste into the focused window (needs paste_command)
+  v           view the whole entry
+  d           decode base64 or hex
+  b           show the entry's raw bytes as a hex dump
+  p           pin or unpin
+  t           move the entry to the top, as if just copied
+  B           burn after copy: delete the entry the next time it is copied
+  l           edit the label
+  n           edit the note (shown under the preview, never copied)
+  w           write the entry to a file
+  x           delete the entry
+  X           delete all unpinned entries
+  /           search
+  f           search for what is on the clipboard now
+  s           snippets
+  i           history file size and entry counts
+  S           sort by recency, A to Z, use count or length, in turn
+  m           show only the list (no preview or footer), or everything again
+  Tab         next history (when several are configured)
+  ?           this help
+  q           quit
+
+Search
+  @today @yesterday @week @month @2024-03-15   only entries from that date range
+  @primary @clipboard   only entries captured from that selection
+  Ctrl+P/N    previous and next search
+  Ctrl+W      match whole words only
+  Ctrl+S      treat tabs, newlines and repeated spaces as one space
+  Enter       copy the selected match and show it in the full list
+  Esc         back to the history, where the cursor was before searching
+
+Snippets
+  Enter       copy the snippet
+  a           add a snippet: a name (optional), then its content
+  e           edit the snippet
+  x           delete the snippet
+  Ctrl+J      start a new line in the content being typed
+  Esc         back to the history
+
+Views
+  ↑/↓ PgUp/PgDn Home/End   scroll
+  Esc         close
+";
+
+// Actions that lose data. With `confirm_destructive` they wait in `InputMode::Confirm` for a
+// y/n answer; otherwise they run straight away.
+enum Destructive {
+    Delete { id: u64 },
+    Clear,
+    // Writing the entry with id `id` over the file at `path`.
+    Overwrite { id: u64, path: PathBuf },
+}
+
+impl Destructive {
+    fn prompt(&self) -> &'static str {
+        match self {
+            Destructive::Delete { .. } => "Delete this entry? y/n",
+            Destructive::Clear => "Delete all unpinned entries? y/n",
+            Destructive::Overwrite { .. } => "That file exists. Overwrite it? y/n",
+        }
+    }
+
+    // Returns the message for the status line.
+    fn apply(self, history: &mut ClipHistory) -> String {
+        match self {
+            Destructive::Delete { id } => {
+                history.remove(id);
+                "Entry deleted".to_string()
+            }
+            Destructive::Clear => format!("Deleted {} entries", history.clear()),
+            Destructive::Overwrite { id, path } => {
+                match history.entries().iter().find(|e| e.id == id) {
+                    Some(entry) => write_entry(entry, &path),
+                    None => "Entry no longer exists".to_string(),
+                }
+            }
+        }
+    }
 }
 
 enum InputMode {
     Normal,
+    Confirm(Destructive),
     Searching(String),
+    Snippets(Option<SnippetEdit>),
+    // Editing the label of the entry with id `id`.
+    Labeling { id: u64, buffer: String },
+    // Editing the note of the entry with id `id`.
+    Noting { id: u64, buffer: String },
+    // Waiting for the key naming how to quote the entry with id `id` as it is copied.
+    Quoting { id: u64 },
+    // Typing the path to write the content of the entry with id `id` to.
+    Saving { id: u64, buffer: String },
+}
+
+// Smaller terminals only get a "too small" message: the list needs its borders and a row.
+const MIN_WIDTH: u16 = 20;
+const MIN_HEIGHT: u16 = 3;
+// Below these sizes the optional panes are dropped so the list keeps usable space.
+const PREVIEW_MIN_WIDTH: u16 = 80;
+const FOOTER_MIN_HEIGHT: u16 = 10;
+const LIST_MIN_HEIGHT: u16 = 3;
+const NOTE_MAX_LINES: usize = 4;
+// Rows used when `alternate_screen` is off, or fewer on a shorter terminal.
+const INLINE_HEIGHT: u16 = 20;
+// Narrower lists fall back to `list_template` rows, as the columns would leave no room for content.
+const COLUMNS_MIN_WIDTH: u16 = 50;
+
+struct AppLayout {
+    live: Option<Rect>,
+    list: Rect,
+    preview: Option<Rect>,
+    footer: Option<Rect>,
+}
+
+// `compact` gives the whole area to the list.
+fn compute_layout(area: Rect, compact: bool) -> AppLayout {
+    if compact {
+        return AppLayout {
+            live: None,
+            list: area,
+            preview: None,
+            footer: None,
+        };
+    }
+    let (body, footer) = if area.height >= FOOTER_MIN_HEIGHT {
+        let [body, footer] =
+            Layout::vertical([Constraint::Min(LIST_MIN_HEIGHT), Constraint::Length(1)]).areas(area);
+        (body, Some(footer))
+    } else {
+        (area, None)
+    };
+
+    let (list, preview) = if body.width >= PREVIEW_MIN_WIDTH {
+        let [list, preview] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(body);
+        (list, Some(preview))
+    } else {
+        (body, None)
+    };
+
+    let (live, list) = if list.height > LIST_MIN_HEIGHT {
+        let [live, list] =
+            Layout::vertical([Constraint::Length(1), Constraint::Min(0)]).areas(list);
+        (Some(live), list)
+    } else {
+        (None, list)
+    };
+
+    AppLayout {
+        live,
+        list,
+        preview,
+        footer,
+    }
+}
+
+fn parse_positive<T: std::str::FromStr + Default + PartialEq>(
+    flag: &str,
+    value: Option<String>,
+) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("{} requires a value", flag))?;
+    match value.parse::<T>() {
+        Ok(n) if n != T::default() => Ok(n),
+        _ => Err(format!(
+            "{} expects a positive integer, got '{}'",
+            flag, value
+        )),
+    }
+}
+
+// Human durations such as `90s`, `30m`, `12h`, `3d` or `2w`.
+fn parse_duration(text: &str) -> Result<TimeDelta, String> {
+    let invalid = || format!("invalid duration '{}' (expected e.g. 30m, 12h, 3d)", text);
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(invalid)?;
+    let (amount, unit) = text.split_at(split);
+    let amount: i64 = amount.parse().map_err(|_| invalid())?;
+    let delta = match unit {
+        "s" => TimeDelta::try_seconds(amount),
+        "m" => TimeDelta::try_minutes(amount),
+        "h" => TimeDelta::try_hours(amount),
+        "d" => TimeDelta::try_days(amount),
+        "w" => TimeDelta::try_weeks(amount),
+        _ => None,
+    };
+    delta.ok_or_else(invalid)
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<CliArgs, String> {
+    let mut cli = CliArgs::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--poll" => cli.poll_interval_ms = Some(parse_positive("--poll", args.next())?),
+            "--max" => cli.max_entries = Some(parse_positive("--max", args.next())?),
+            "--config" => {
+                let value = args.next().ok_or("--config requires a value")?;
+                cli.config_path = Some(PathBuf::from(value));
+            }
+            "--older-than" => {
+                let value = args.next().ok_or("--older-than requires a value")?;
+                cli.older_than = Some(parse_duration(&value)?);
+            }
+            "--read-only" => cli.read_only = true,
+            "--search" => {
+                let value = args.next().ok_or("--search requires a value")?;
+                cli.search = Some(value);
+            }
+            "--word" => cli.search_options.whole_word = true,
+            "--any-spacing" => cli.search_options.collapse_whitespace = true,
+            "-h" | "--help" => cli.help = true,
+            "-V" | "--version" => cli.version = true,
+            _ if arg.starts_with('-') => return Err(format!("unknown option '{}'", arg)),
+            _ if cli.command.is_none() => cli.command = Some(arg),
+            _ => cli.operands.push(arg),
+        }
+    }
+
+    let takes_operands = matches!(cli.command.as_deref(), Some("search" | "get"));
+    if let Some(operand) = cli.operands.first().filter(|_| !takes_operands) {
+        return Err(format!("unexpected argument '{}'", operand));
+    }
+    Ok(cli)
+}
+
+// Choices made in the UI that outlast a run, kept in `state.json` rather than in the
+// user's own config file.
+#[derive(Serialize, Deserialize, Default)]
+struct UiState {
+    #[serde(default)]
+    sort: Option<SortOrder>,
+    /// The entry under the cursor at the last exit, for `initial_selection: last_viewed`.
+    #[serde(default)]
+    last_viewed: Option<ViewedEntry>,
+    /// How far below the top of the list that entry was drawn, so it opens in the same place.
+    #[serde(default)]
+    last_viewed_row: usize,
+}
+
+// Ids are handed out afresh each run, so the entry is found again by when it was captured.
+#[derive(Serialize, Deserialize, PartialEq)]
+struct ViewedEntry {
+    timestamp: String,
+    seq: u64,
+}
+
+impl ViewedEntry {
+    fn of(entry: &Entry) -> Self {
+        ViewedEntry {
+            timestamp: entry.timestamp.clone(),
+            seq: entry.seq,
+        }
+    }
+}
+
+fn load_ui_state() -> UiState {
+    let data = fs::read_to_string(data_dir().join("state.json")).unwrap_or_default();
+    serde_json::from_str(&data).unwrap_or_default()
+}
+
+fn save_ui_state(state: &UiState) {
+    let _ = fs::write(
+        data_path("state.json"),
+        serde_json::to_string(state).unwrap(),
+    );
+}
+
+fn load_snippets() -> Vec<Snippet> {
+    let data = fs::read_to_string(data_dir().join("snippets.json")).unwrap_or_default();
+    serde_json::from_str(&data).unwrap_or_default()
+}
+
+// Written whole through a temporary file, so a failed write leaves the old library in place.
+fn save_snippets(snippets: &[Snippet]) -> Result<(), String> {
+    let path = data_path("snippets.json");
+    let temp = path.with_extension("tmp");
+    fs::write(&temp, serde_json::to_string_pretty(snippets).unwrap())
+        .and_then(|()| fs::rename(&temp, &path))
+        .map_err(|e| format!("Couldn't save {}: {}", path.display(), e))
+}
+
+// The newest `max` entries of `hist` and any older pins, for `--max`; all of them without one.
+fn limit_entries(hist: &[Entry], max: Option<usize>) -> Vec<Entry> {
+    let start = max.map_or(0, |max| hist.len().saturating_sub(max));
+    hist.iter()
+        .enumerate()
+        .filter(|(i, e)| *i >= start || e.pinned)
+        .map(|(_, e)| e.clone())
+        .collect()
+}
+
+// Entries in the order they are listed for the current mode; list indices refer to this.
+fn visible_entries<'a>(
+    hist: &'a [Entry],
+    input_mode: &InputMode,
+    search_options: SearchOptions,
+    sort: SortOrder,
+    config: &Config,
+) -> Vec<&'a Entry> {
+    listed_entries(hist, input_mode, search_options, sort, config).0
+}
+
+// The entries `visible_entries` lists, and how many more matched a search than
+// `max_search_results` let through.
+fn listed_entries<'a>(
+    hist: &'a [Entry],
+    input_mode: &InputMode,
+    search_options: SearchOptions,
+    sort: SortOrder,
+    config: &Config,
+) -> (Vec<&'a Entry>, usize) {
+    let query = match input_mode {
+        InputMode::Searching(query) => Some(Query::parse(query, search_options)),
+        _ => None,
+    };
+    let mut entries: Vec<&Entry> = hist
+        .iter()
+        .rev()
+        .filter(|e| config.show_whitespace_entries || !e.content.trim().is_empty())
+        .filter(|e| match input_mode {
+            InputMode::Normal
+            | InputMode::Confirm(_)
+            | InputMode::Quoting { .. }
+            | InputMode::Saving { .. }
+            | InputMode::Labeling { .. }
+            | InputMode::Noting { .. } => true,
+            InputMode::Searching(_) => query
+                .as_ref()
+                .is_some_and(|q| !q.is_empty() && q.matches(e)),
+            InputMode::Snippets(_) => false,
+        })
+        .collect();
+    // Stable sorts, so ties stay newest first.
+    match sort {
+        SortOrder::Recent => {}
+        SortOrder::Alphabetical => entries.sort_by_cached_key(|e| e.content.to_lowercase()),
+        SortOrder::Usage => entries.sort_by_key(|e| Reverse(e.use_count)),
+        SortOrder::Length => entries.sort_by_cached_key(|e| Reverse(e.content.chars().count())),
+    }
+    let mut unlisted = 0;
+    if query.is_some() && config.max_search_results > 0 {
+        unlisted = entries.len().saturating_sub(config.max_search_results);
+        entries.truncate(config.max_search_results);
+    }
+    (entries, unlisted)
+}
+
+// Whitespace-only clips that reach the list: with the default `skip_empty` these are
+// spaces/tabs, possibly spread over several lines.
+fn describe_whitespace(content: &str) -> String {
+    if content.contains('\n') {
+        let lines = content.lines().count().max(1);
+        format!(
+            "({} blank line{})",
+            lines,
+            if lines == 1 { "" } else { "s" }
+        )
+    } else {
+        let chars = content.chars().count();
+        format!(
+            "({} whitespace char{})",
+            chars,
+            if chars == 1 { "" } else { "s" }
+        )
+    }
+}
+
+fn display_timestamp(ts: &str) -> String {
+    match parse_timestamp(ts) {
+        Some(dt) => dt.format(DISPLAY_TIME_FORMAT).to_string(),
+        None => ts.to_string(),
+    }
+}
+
+// "copied 7 times, last 2h ago" for the preview, or `None` for an entry never copied back out.
+// Entries from before copies were timed just give the count.
+fn usage_summary(entry: &Entry) -> Option<String> {
+    if entry.use_count == 0 {
+        return None;
+    }
+    let plural = if entry.use_count == 1 { "" } else { "s" };
+    let mut summary = format!("copied {} time{}", entry.use_count, plural);
+    if let Some(used) = entry.last_used.as_deref().and_then(parse_timestamp) {
+        let secs = (Local::now() - used).num_seconds().max(0);
+        let ago = match secs {
+            0..60 => "just now".to_string(),
+            60..3600 => format!("{}m ago", secs / 60),
+            3600..86400 => format!("{}h ago", secs / 3600),
+            _ => format!("{}d ago", secs / 86400),
+        };
+        summary.push_str(&format!(", last {}", ago));
+    }
+    Some(summary)
+}
+
+fn format_size(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1}{}", size, UNITS[unit])
+    }
+}
+
+// Largest prefix of `text` no longer than `max_bytes` that ends on a char boundary.
+fn truncate_bytes(text: &str, max_bytes: usize) -> &str {
+    if text.len() <= max_bytes {
+        return text;
+    }
+    let mut end = max_bytes;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+// Wrapping a multi-megabyte clip every frame stalls the UI, so the preview pane only gets a
+// bounded prefix; the full text is shown in the view opened with `v`. With `max_lines` the
+// prefix also stops after that many rows, counting lines as they wrap at `width`.
+fn preview_content(
+    content: &str,
+    max_bytes: usize,
+    max_lines: usize,
+    width: usize,
+) -> Cow<'_, str> {
+    if content.len() > max_bytes {
+        let shown = truncate_bytes(content, max_bytes);
+        let shown = &shown[..fit_rows(shown, max_lines, width)];
+        return Cow::Owned(format!(
+            "{}\n…(truncated, {} total — press v to view all)",
+            shown,
+            format_size(content.len())
+        ));
+    }
+    let end = fit_rows(content, max_lines, width);
+    if end == content.len() {
+        return Cow::Borrowed(content);
+    }
+    Cow::Owned(format!(
+        "{}\n… +{} more lines (press v to view all)",
+        content[..end].trim_end_matches(['\r', '\n']),
+        content[end..].lines().count()
+    ))
+}
+
+// Near-duplicates below this don't get a hint; above it, the edit distance is small enough
+// that the entries are likely versions of one another.
+const SIMILAR_MIN: f64 = 0.8;
+const SIMILARITY_CHARS: usize = 1000;
+
+// The entry next to `visible[idx]` (above or below) most like it, as a percentage and its index,
+// if it is similar enough to be worth pointing out.
+fn similar_neighbor(visible: &[&Entry], idx: usize) -> Option<(u32, usize)> {
+    let entry = visible.get(idx)?;
+    [idx.checked_sub(1), Some(idx + 1)]
+        .into_iter()
+        .flatten()
+        .filter_map(|n| {
+            Some((
+                similarity(&entry.content, &visible.get(n)?.content, SIMILARITY_CHARS),
+                n,
+            ))
+        })
+        .filter(|(score, _)| *score >= SIMILAR_MIN)
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(score, n)| ((score * 100.0).floor() as u32, n))
+}
+
+fn skip_lines(text: &str, lines: usize) -> &str {
+    let skipped: usize = text.split_inclusive('\n').take(lines).map(str::len).sum();
+    &text[skipped..]
+}
+
+// Byte length of the start of `text` that fills at most `max_rows` rows `width` wide; all of
+// it when `max_rows` is 0. A line that doesn't fit is cut where the last row ends.
+fn fit_rows(text: &str, max_rows: usize, width: usize) -> usize {
+    if max_rows == 0 {
+        return text.len();
+    }
+    let width = width.max(1);
+    let (mut rows, mut end) = (0, 0);
+    for line in text.split_inclusive('\n') {
+        let chars = line.trim_end_matches(['\r', '\n']).chars().count();
+        let line_rows = chars.div_ceil(width).max(1);
+        if rows + line_rows > max_rows {
+            let room = (max_rows - rows) * width;
+            return end + line.char_indices().nth(room).map_or(line.len(), |(i, _)| i);
+        }
+        rows += line_rows;
+        end += line.len();
+    }
+    end
+}
+
+// List rows show only the start of the first line so huge or multi-line clips stay one row.
+fn row_text(content: &str) -> String {
+    const MAX_ROW_CHARS: usize = 200;
+    let first = content.lines().next().unwrap_or("");
+    let mut row: String = first.chars().take(MAX_ROW_CHARS).collect();
+    if row.len() < content.trim_end_matches(['\r', '\n']).len() {
+        row.push('…');
+    }
+    row
+}
+
+// The path a clip names, if it looks like one: absolute, `~/`-relative or a `file://` URL, on a
+// single line. `//` and `/*` comments are not paths, and neither is text whose first component
+// has whitespace in it, like `/ note`. Whether it exists is only checked when it is acted on,
+// as this runs for every row drawn.
+fn clip_path(content: &str) -> Option<PathBuf> {
+    let text = content.trim();
+    if text.is_empty() || text.contains('\n') {
+        return None;
+    }
+    let text = text.strip_prefix("file://").unwrap_or(text);
+    let first = text
+        .strip_prefix("~/")
+        .unwrap_or(text)
+        .split(['/', '\\'])
+        .find(|component| !component.is_empty());
+    if text.starts_with("//") || text.starts_with("/*") || first?.contains(char::is_whitespace) {
+        return None;
+    }
+    if let Some(rest) = text.strip_prefix("~/") {
+        return Some(dirs::home_dir()?.join(rest));
+    }
+    let windows_drive = text.as_bytes().get(1..3) == Some(b":\\".as_slice());
+    (text.starts_with('/') || windows_drive).then(|| PathBuf::from(text))
+}
+
+// A path typed into clipb: `~` is the home directory, and relative paths start from the
+// directory clipb was run in.
+fn expand_path(text: &str) -> PathBuf {
+    let home = dirs::home_dir();
+    let path = match (text.strip_prefix('~'), home) {
+        (Some(""), Some(home)) => home,
+        (Some(rest), Some(home)) if rest.starts_with(['/', '\\']) => home.join(&rest[1..]),
+        _ => PathBuf::from(text),
+    };
+    match std::env::current_dir() {
+        Ok(dir) if path.is_relative() => dir.join(path),
+        _ => path,
+    }
+}
+
+// Writes `entry`'s content to `path` exactly as stored. Returns the message for the status line.
+fn write_entry(entry: &Entry, path: &Path) -> String {
+    match fs::write(path, &entry.content) {
+        Ok(()) => format!(
+            "Saved {} to {}",
+            format_size(entry.content.len()),
+            path.display()
+        ),
+        Err(e) => format!("Couldn't write {}: {}", path.display(), e),
+    }
+}
+
+// Hands `target` to the desktop's default handler without waiting for it.
+fn open_in_system(target: &Path) -> Result<(), String> {
+    let opener = if cfg!(target_os = "macos") {
+        "open"
+    } else if cfg!(windows) {
+        "explorer"
+    } else {
+        "xdg-open"
+    };
+    let mut command = Command::new(opener);
+    command.arg(target);
+    // Some openers stay in the foreground until the application they started quits, so this
+    // one is left to run as long as it likes.
+    match run_with_timeout(command, opener, None) {
+        // Explorer exits with 1 even when it opened the path.
+        Err(e) if cfg!(windows) && e.ends_with("(exit code: 1)") => Ok(()),
+        result => result,
+    }
+}
+
+// Deletes `entry` if it is marked burn-after-copy, now that it has been copied out. Returns
+// the footer message saying so.
+fn burn_after_copy(hist: &mut ClipHistory, entry: &Entry) -> Option<String> {
+    if !entry.burn || hist.read_only() {
+        return None;
+    }
+    hist.remove(entry.id);
+    Some("Copied and deleted (burn after copy)".to_string())
+}
+
+// Everything that follows copying `entry` out, whichever key did it: the `log_usage` record (as
+// `action`), then either burning the entry or counting the use, which may pin it. Returns the
+// footer message for whichever of those has something to say. Burn entries are never logged:
+// the point of them is that nothing is left behind once they are used.
+fn after_copy(
+    history: &Mutex<ClipHistory>,
+    entry: &Entry,
+    action: &str,
+    config: &Config,
+) -> Option<String> {
+    if config.log_usage && !entry.burn {
+        usage::log(entry, action, config.timestamp_utc);
+    }
+    let mut hist = history.lock().unwrap();
+    if entry.burn {
+        return burn_after_copy(&mut hist, entry);
+    }
+    let now = now_timestamp(config.timestamp_utc);
+    (!hist.read_only() && hist.record_use(entry.id, now, config.auto_pin_uses))
+        .then(|| "Pinned after repeated use".to_string())
+}
+
+// The `reference_template` text for `entry`, for notes that cite a clip rather than paste it.
+fn entry_reference(entry: &Entry, config: &Config) -> String {
+    fill_template(
+        &config.reference_template,
+        &[
+            ("timestamp", &display_timestamp(&entry.timestamp)),
+            ("content", &row_text(&entry.content)),
+            ("label", entry.label.as_deref().unwrap_or("")),
+            ("source", entry.source.as_deref().unwrap_or("")),
+        ],
+    )
+}
+
+// Read-only summary of the history on screen, for deciding when to prune.
+fn info_view(history: &ClipHistory) -> View {
+    let entries = history.entries();
+    let path = history.path();
+    let size = fs::metadata(&path).map_or(0, |m| m.len() as usize);
+    let times: Vec<_> = entries
+        .iter()
+        .filter_map(|e| parse_timestamp(&e.timestamp))
+        .collect();
+    let format_time = |t: Option<&DateTime<Local>>| {
+        t.map_or("-".to_string(), |t| {
+            t.format(DISPLAY_TIME_FORMAT).to_string()
+        })
+    };
+    let content = format!(
+        "File     {}\nSize     {}\nEntries  {}\nPinned   {}\nImages   {}\nOldest   {}\nNewest   {}\n",
+        path.display(),
+        format_size(size),
+        entries.len(),
+        entries.iter().filter(|e| e.pinned).count(),
+        entries.iter().filter(|e| e.image.is_some()).count(),
+        format_time(times.iter().min()),
+        format_time(times.iter().max()),
+    );
+    View {
+        title: "History info".to_string(),
+        content,
+        scroll: 0,
+    }
 }
 
-fn load_config() -> Config {
-    let mut path = config_dir().unwrap_or_else(|| PathBuf::from("."));
-    path.push("clipman/config.json");
+// Tries base64 then hex; text results are shown as-is, binary ones as a hex dump.
+fn decode_view(content: &str) -> Result<View, String> {
+    let decoded = [
+        ("base64", decode_base64(content)),
+        ("hex", decode_hex(content)),
+    ];
+    let text = decoded.iter().find_map(|(kind, bytes)| {
+        let text = String::from_utf8(bytes.clone()?).ok()?;
+        Some(View {
+            title: format!("Decoded {}", kind),
+            content: text,
+            scroll: 0,
+        })
+    });
+    if let Some(view) = text {
+        return Ok(view);
+    }
+    match decoded.iter().find(|(_, bytes)| bytes.is_some()) {
+        Some((kind, Some(bytes))) => Ok(View {
+            title: format!("Decoded {} ({} bytes, binary)", kind, bytes.len()),
+            content: hex_dump(bytes),
+            scroll: 0,
+        }),
+        _ => Err("Not valid base64 or hex".to_string()),
+    }
+}
+
+fn set_clipboard(text: &str, restored: &Restored) -> Result<(), Box<dyn Error + Send + Sync>> {
+    restore_with(text, &restored.clipboard, |clipboard| {
+        clipboard.set_text(text)
+    })
+}
+
+// What restoring `content` puts on the clipboard; the stored entry itself is left as it is.
+// With the `*_on_copy` options off (the default) this is `content`, byte for byte.
+fn copy_text<'a>(content: &'a str, config: &Config) -> Cow<'a, str> {
+    let mut text = if config.strip_ansi_on_copy {
+        Cow::Owned(strip_ansi(content))
+    } else {
+        Cow::Borrowed(content)
+    };
+    text = match text {
+        Cow::Borrowed(s) => config.copied_text(s),
+        Cow::Owned(s) => Cow::Owned(config.copied_text(&s).into_owned()),
+    };
+    if config.strip_trailing_newline_on_copy {
+        // Only one, so deliberate blank lines at the end survive.
+        let trimmed = text
+            .strip_suffix("\r\n")
+            .or_else(|| text.strip_suffix('\n'))
+            .map(str::len);
+        if let Some(len) = trimmed {
+            match &mut text {
+                Cow::Borrowed(s) => *s = &s[..len],
+                Cow::Owned(s) => s.truncate(len),
+            }
+        }
+    }
+    text
+}
+
+fn copy_entry(
+    entry: &Entry,
+    config: &Config,
+    restored: &Restored,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let text = copy_text(&entry.content, config);
+    // Marked like clipboard writes, since `capture_primary` would store it again otherwise.
+    if config.restore_target != RestoreTarget::Clipboard && entry.image.is_none() {
+        restore_with(&text, &restored.primary, |clipboard| {
+            clipboard.set_primary_text(&text)
+        })?;
+        if config.restore_target == RestoreTarget::Primary {
+            return Ok(());
+        }
+    }
+    if let Some(html) = &entry.html {
+        return restore_with(&text, &restored.clipboard, |clipboard| {
+            clipboard.set_html(html, &text)
+        });
+    }
+    let Some(name) = &entry.image else {
+        return set_clipboard(&text, restored);
+    };
+    let image = images::load(name).ok_or("stored image is missing")?;
+    restore_with(name, &restored.clipboard, |clipboard| {
+        clipboard.set_image(&image)
+    })
+}
+
+// Marks `key` as clipb's own write before making it, so the poll thread doesn't capture it again.
+fn restore_with(
+    key: &str,
+    restored: &Mutex<Option<String>>,
+    write: impl FnOnce(&mut SystemClipboard) -> Result<(), Box<dyn Error + Send + Sync>>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    *restored.lock().unwrap() = Some(key.to_string());
+    let result = SystemClipboard::new()
+        .map_err(Into::into)
+        .and_then(|mut clipboard| write(&mut clipboard));
+    if result.is_err() {
+        *restored.lock().unwrap() = None;
+    }
+    result
+}
+
+// Gives focus time to return to the target window before the paste command fires.
+const PASTE_DELAY: Duration = Duration::from_millis(150);
+
+fn run_paste_command(command: &str, timeout: Duration) -> Result<(), String> {
+    thread::sleep(PASTE_DELAY);
+    run_with_timeout(shell_command(command), "Paste command", Some(timeout))
+}
+
+// Runs `job` on a thread of its own so a slow external command can't freeze the UI; an error
+// comes back through `errors` for the footer.
+fn in_background(
+    errors: &Sender<String>,
+    job: impl FnOnce() -> Result<(), String> + Send + 'static,
+) {
+    let errors = errors.clone();
+    spawn(move || {
+        if let Err(e) = job() {
+            let _ = errors.send(e);
+        }
+    });
+}
+
+// All styling goes through here so `no_color`/`NO_COLOR` can swap colors for attributes.
+struct Theme {
+    highlight: Style,
+    label: Style,
+    meta: Style,
+    pin: Style,
+    live_label: Style,
+    live_text: Style,
+    // The characters of a row a search matched.
+    matched: Style,
+}
+
+impl Theme {
+    fn new(color: bool) -> Self {
+        if color {
+            Theme {
+                highlight: Style::default().bg(Color::DarkGray),
+                label: Style::default()
+                    .fg(Color::Cyan)
+                    .add_modifier(Modifier::BOLD),
+                meta: Style::default().fg(Color::DarkGray),
+                pin: Style::default().fg(Color::Yellow),
+                live_label: Style::default().fg(Color::Black).bg(Color::Cyan),
+                live_text: Style::default().add_modifier(Modifier::ITALIC),
+                matched: Style::default()
+                    .fg(Color::Yellow)
+                    .add_modifier(Modifier::BOLD),
+            }
+        } else {
+            Theme {
+                highlight: Style::default().add_modifier(Modifier::REVERSED),
+                label: Style::default().add_modifier(Modifier::BOLD),
+                meta: Style::default().add_modifier(Modifier::DIM),
+                pin: Style::default().add_modifier(Modifier::BOLD),
+                live_label: Style::default().add_modifier(Modifier::REVERSED),
+                live_text: Style::default().add_modifier(Modifier::ITALIC),
+                matched: Style::default().add_modifier(Modifier::BOLD | Modifier::UNDERLINED),
+            }
+        }
+    }
+}
+
+// Terminal graphics protocols used to draw image thumbnails in the preview pane.
+#[derive(Clone, Copy, PartialEq)]
+enum Graphics {
+    Kitty,
+    Sixel,
+}
+
+// Asking the terminal means reading its reply off stdin alongside key events, so go by what it
+// advertises in the environment instead.
+fn graphics_protocol() -> Option<Graphics> {
+    let var = |name| std::env::var(name).unwrap_or_default();
+    let (term, program) = (var("TERM"), var("TERM_PROGRAM"));
+    if std::env::var_os("KITTY_WINDOW_ID").is_some()
+        || term.contains("kitty")
+        || term.contains("ghostty")
+        || program == "WezTerm"
+    {
+        Some(Graphics::Kitty)
+    } else if term.contains("sixel") || term.starts_with("foot") || term.starts_with("mlterm") {
+        Some(Graphics::Sixel)
+    } else {
+        None
+    }
+}
+
+// Pixel size of one cell when the terminal reports it, otherwise a typical 10×20.
+fn cell_pixels() -> (u32, u32) {
+    match crossterm::terminal::window_size() {
+        Ok(size) if size.width > 0 && size.height > 0 && size.columns > 0 && size.rows > 0 => (
+            (size.width / size.columns).max(1) as u32,
+            (size.height / size.rows).max(1) as u32,
+        ),
+        _ => (10, 20),
+    }
+}
+
+// Raw RGBA pixels through the kitty graphics protocol, sent in the chunks it requires.
+fn kitty_image(image: &Image) -> String {
+    let data = encode_base64(&image.rgba);
+    let chunks: Vec<&[u8]> = data.as_bytes().chunks(4096).collect();
+    let mut out = String::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = u8::from(i + 1 < chunks.len());
+        if i == 0 {
+            out.push_str(&format!(
+                "\x1b_Ga=T,f=32,s={},v={},C=1,q=2,m={};",
+                image.width, image.height, more
+            ));
+        } else {
+            out.push_str(&format!("\x1b_Gm={};", more));
+        }
+        out.push_str(std::str::from_utf8(chunk).unwrap());
+        out.push_str("\x1b\\");
+    }
+    out
+}
+
+// Sixel with a fixed 6×6×6 color cube; mostly transparent pixels are left unpainted.
+fn sixel_image(image: &Image) -> String {
+    let level = |c: u8| (c as usize * 5 + 127) / 255;
+    let color = |x: usize, y: usize| {
+        let px = &image.rgba[(y * image.width + x) * 4..][..4];
+        (px[3] >= 128).then(|| level(px[0]) * 36 + level(px[1]) * 6 + level(px[2]))
+    };
+
+    let mut out = format!("\x1bP0;1;0q\"1;1;{};{}", image.width, image.height);
+    for i in 0..216 {
+        let percent = |l: usize| l * 100 / 5;
+        out.push_str(&format!(
+            "#{};2;{};{};{}",
+            i,
+            percent(i / 36),
+            percent(i / 6 % 6),
+            percent(i % 6)
+        ));
+    }
+    for band in (0..image.height).step_by(6) {
+        let mut columns: Vec<Option<Vec<u8>>> = vec![None; 216];
+        for y in band..(band + 6).min(image.height) {
+            for x in 0..image.width {
+                if let Some(c) = color(x, y) {
+                    columns[c].get_or_insert_with(|| vec![0; image.width])[x] |= 1 << (y - band);
+                }
+            }
+        }
+        for (c, bits) in columns.iter().enumerate() {
+            let Some(bits) = bits else { continue };
+            out.push_str(&format!("#{}", c));
+            // Run-length encode repeated sixels.
+            let mut x = 0;
+            while x < bits.len() {
+                let run = bits[x..].iter().take_while(|&&b| b == bits[x]).count();
+                let sixel = (63 + bits[x]) as char;
+                if run > 3 {
+                    out.push_str(&format!("!{}{}", run, sixel));
+                } else {
+                    out.extend(std::iter::repeat_n(sixel, run));
+                }
+                x += run;
+            }
+            out.push('$');
+        }
+        out.push('-');
+    }
+    out.push_str("\x1b\\");
+    out
+}
+
+// Drawn over the preview pane after ratatui has flushed its frame.
+fn draw_thumbnail(
+    out: &mut impl Write,
+    graphics: Graphics,
+    name: &str,
+    area: Rect,
+) -> io::Result<()> {
+    let (cell_width, cell_height) = cell_pixels();
+    let Some(image) = images::thumbnail(
+        name,
+        area.width as u32 * cell_width,
+        area.height as u32 * cell_height,
+    ) else {
+        return Ok(());
+    };
+    let data = match graphics {
+        Graphics::Kitty => kitty_image(&image),
+        Graphics::Sixel => sixel_image(&image),
+    };
+    execute!(out, MoveTo(area.x, area.y))?;
+    out.write_all(data.as_bytes())?;
+    out.flush()
+}
+
+// https://no-color.org: any non-empty value disables color.
+fn color_enabled(config: &Config) -> bool {
+    !config.no_color && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+}
+
+// First row to show so that `selected` stays in view, scrolling no more than needed.
+fn scroll_offset(offset: usize, selected: usize, len: usize, rows: usize) -> usize {
+    let selected = selected.min(len.saturating_sub(1));
+    let offset = if selected < offset {
+        selected
+    } else if rows > 0 && selected >= offset + rows {
+        selected + 1 - rows
+    } else {
+        offset
+    };
+    offset.min(len.saturating_sub(rows))
+}
+
+// A row of the history list: an entry, by its index in the visible list, or with
+// `group_by_date` the header of the day the entries below it were captured.
+enum ListRow {
+    Header(String),
+    Entry(usize),
+}
+
+// Where the entry at `idx` of `entries` is drawn in the full list, counting any date headers.
+fn display_row(entries: &[&Entry], idx: usize, sort: SortOrder, config: &Config) -> usize {
+    if !config.group_by_date || sort != SortOrder::Recent {
+        return idx;
+    }
+    date_rows(entries)
+        .iter()
+        .position(|row| matches!(row, ListRow::Entry(i) if *i == idx))
+        .unwrap_or(idx)
+}
+
+// `entries` (newest first) with a header before each run captured on the same day.
+fn date_rows(entries: &[&Entry]) -> Vec<ListRow> {
+    let today = Local::now().date_naive();
+    let mut rows = Vec::new();
+    let mut current = None;
+    for (idx, entry) in entries.iter().enumerate() {
+        let day = parse_timestamp(&entry.timestamp).map(|ts| ts.date_naive());
+        if current != Some(day) {
+            let header = match day {
+                Some(day) if day == today => "Today".to_string(),
+                Some(day) if today.pred_opt() == Some(day) => "Yesterday".to_string(),
+                Some(day) => day.format("%a %Y-%m-%d").to_string(),
+                None => "Unknown date".to_string(),
+            };
+            rows.push(ListRow::Header(header));
+            current = Some(day);
+        }
+        rows.push(ListRow::Entry(idx));
+    }
+    rows
+}
+
+fn snippet_list_item<'a>(s: &'a Snippet, theme: &Theme) -> ListItem<'a> {
+    match &s.name {
+        Some(name) => ListItem::new(Line::from(vec![
+            Span::styled(name.as_str(), theme.label),
+            Span::raw(format!("  {}", s.content)),
+        ])),
+        None => ListItem::new(s.content.as_str()),
+    }
+}
+
+// Lays a row out according to `list_template`, styling each field the way the default
+// layout does. `index` is the 1-based position in the visible list; what `search` matches in
+// the preview is highlighted.
+fn to_list_item<'a>(
+    e: &'a Entry,
+    index: usize,
+    template: &str,
+    quick_key: Option<String>,
+    search: Option<&Query>,
+    theme: &Theme,
+) -> ListItem<'a> {
+    let mut spans: Vec<Span> = quick_key
+        .map(|key| Span::styled(key, theme.pin))
+        .into_iter()
+        .collect();
+    spans.extend(template_spans(e, index, template, search, theme));
+    ListItem::new(Line::from(spans))
+}
+
+// `text` split into spans, with the parts `query` matches in the `matched` style.
+fn highlight_matches(text: String, query: &Query, theme: &Theme) -> Vec<Span<'static>> {
+    let mut spans = Vec::new();
+    let mut end = 0;
+    for range in query.match_ranges(&text) {
+        if range.start > end {
+            spans.push(Span::raw(text[end..range.start].to_string()));
+        }
+        spans.push(Span::styled(text[range.clone()].to_string(), theme.matched));
+        end = range.end;
+    }
+    if end < text.len() {
+        spans.push(Span::raw(text[end..].to_string()));
+    }
+    spans
+}
+
+// The `[1] `..`[9] ` marker `quick_copy` puts before the top rows; later rows get as much
+// blank space so the list stays aligned.
+fn quick_copy_key(index: usize, config: &Config) -> Option<String> {
+    match index {
+        _ if !config.quick_copy => None,
+        1..=9 => Some(format!("[{}] ", index)),
+        _ => Some("    ".to_string()),
+    }
+}
+
+// The content column of `list_columns` rows: everything but the index and time.
+const COLUMN_TEMPLATE: &str = "{pin}{label}{tag}{preview}{count}{source}";
+
+// A `list_columns` row: the index right-aligned to `index_width` and the time padded (or cut)
+// to the width of a formatted timestamp, so the content column starts at the same place.
+fn to_column_item<'a>(
+    e: &'a Entry,
+    index: usize,
+    index_width: usize,
+    quick_key: Option<String>,
+    search: Option<&Query>,
+    theme: &Theme,
+) -> ListItem<'a> {
+    let time_width = Local::now()
+        .format(DISPLAY_TIME_FORMAT)
+        .to_string()
+        .chars()
+        .count();
+    let mut spans: Vec<Span> = quick_key
+        .map(|key| Span::styled(key, theme.pin))
+        .into_iter()
+        .collect();
+    spans.extend([
+        Span::styled(format!("{:>1$} ", index, index_width), theme.meta),
+        Span::raw(format!(
+            "{:<1$.1$} │ ",
+            display_timestamp(&e.timestamp),
+            time_width
+        )),
+    ]);
+    spans.extend(template_spans(e, index, COLUMN_TEMPLATE, search, theme));
+    ListItem::new(Line::from(spans))
+}
+
+fn template_spans<'a>(
+    e: &'a Entry,
+    index: usize,
+    template: &str,
+    search: Option<&Query>,
+    theme: &Theme,
+) -> Vec<Span<'a>> {
+    let mut spans = Vec::new();
+    for segment in template_segments(template) {
+        let span = match segment {
+            Segment::Literal(text) => Span::raw(text.to_string()),
+            Segment::Field("index") => Span::raw(index.to_string()),
+            Segment::Field("pin") if e.pinned => Span::styled("★ ", theme.pin),
+            Segment::Field("time") => Span::raw(display_timestamp(&e.timestamp)),
+            Segment::Field("label") => match &e.label {
+                Some(label) => Span::styled(format!("{} ", label), theme.label),
+                None => continue,
+            },
+            Segment::Field("tag") if e.burn => Span::styled("BURN ", theme.pin),
+            Segment::Field("tag") if e.source_selection == Some(Selection::Primary) => {
+                Span::styled("SEL ", theme.meta)
+            }
+            Segment::Field("tag") if e.html.is_some() => Span::styled("HTML ", theme.meta),
+            Segment::Field("tag") if clip_path(&e.content).is_some() => {
+                Span::styled("PATH ", theme.meta)
+            }
+            Segment::Field("preview") if e.content.trim().is_empty() => {
+                Span::raw(describe_whitespace(&e.content))
+            }
+            Segment::Field("preview") => match search {
+                Some(query) => {
+                    spans.extend(highlight_matches(row_text(&e.content), query, theme));
+                    continue;
+                }
+                None => Span::raw(row_text(&e.content)),
+            },
+            Segment::Field("chars") => Span::raw(e.content.chars().count().to_string()),
+            Segment::Field("count") if e.count > 1 => {
+                Span::styled(format!("  ×{}", e.count), theme.meta)
+            }
+            Segment::Field("source") => match &e.source {
+                Some(source) => Span::styled(format!("  {}", source), theme.meta),
+                None => continue,
+            },
+            // Unknown names are rejected by `Config::validate`; the rest are empty here.
+            Segment::Field(_) => continue,
+        };
+        spans.push(span);
+    }
+    spans
+}
+
+const SEARCH_HISTORY_LEN: usize = 50;
+// How much of the clipboard `f` puts in the search box.
+const SEED_MAX_CHARS: usize = 80;
+
+fn remember_query(search_history: &mut Vec<String>, query: &str) {
+    if query.is_empty() || search_history.last().is_some_and(|last| last == query) {
+        return;
+    }
+    search_history.push(query.to_string());
+    if search_history.len() > SEARCH_HISTORY_LEN {
+        search_history.remove(0);
+    }
+}
+
+// Replaces the query with the next older (or newer) remembered one; stepping past the newest
+// clears the buffer again.
+fn recall_query(
+    search_history: &[String],
+    recall: &mut Option<usize>,
+    query: &mut String,
+    older: bool,
+) {
+    if search_history.is_empty() {
+        return;
+    }
+    *recall = match (*recall, older) {
+        (None, true) => Some(search_history.len() - 1),
+        (Some(i), true) => Some(i.saturating_sub(1)),
+        (Some(i), false) if i + 1 < search_history.len() => Some(i + 1),
+        (_, false) => None,
+    };
+    *query = recall
+        .map(|i| search_history[i].clone())
+        .unwrap_or_default();
+}
+
+// Drops entries captured before `now - older_than` from every history file. Stops at the first
+// file that can't be written.
+fn prune_older_than(
+    tabs: &[Option<&str>],
+    older_than: TimeDelta,
+    config: &Config,
+) -> Result<usize, String> {
+    let cutoff = Local::now() - older_than;
+    let mut removed = 0;
+    for tab in tabs {
+        let mut hist = ClipHistory::load(config, *tab);
+        removed += hist.prune_before(cutoff);
+        hist.wait_for_writes();
+        if let Some(e) = hist.take_write_error() {
+            return Err(e);
+        }
+    }
+    Ok(removed)
+}
+
+// `clipb search`: matches numbered by their position in the history, newest first, so the
+// numbers stay valid for `clipb get` whatever the query was.
+fn print_matches(hist: &[Entry], query: &str, options: SearchOptions) -> bool {
+    let query = Query::parse(query, options);
+    let mut found = false;
+    for (index, entry) in hist.iter().rev().enumerate() {
+        if query.matches(entry) {
+            println!("{}\t{}", index + 1, row_text(&entry.content));
+            found = true;
+        }
+    }
+    found
+}
+
+// Asks on the terminal before a destructive CLI command. Without a terminal on stdin there is
+// nobody to ask, so the command goes ahead.
+fn confirm(question: &str) -> io::Result<bool> {
+    if !io::stdin().is_terminal() {
+        return Ok(true);
+    }
+    eprint!("{} [y/N] ", question);
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = match parse_args(std::env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("clipb: {}\nTry 'clipb --help' for usage.", e);
+            std::process::exit(2);
+        }
+    };
+
+    if cli.help || cli.command.as_deref() == Some("help") {
+        print!("{}", USAGE);
+        return Ok(());
+    }
+    if cli.version {
+        println!("clipb {}", env!("CARGO_PKG_VERSION"));
+        return Ok(());
+    }
+
+    let mut config = match &cli.config_path {
+        Some(path) => Config::load_from(path),
+        None => Config::load(),
+    };
+    if let Err(e) = config.validate() {
+        eprintln!("clipb: {}", e);
+        std::process::exit(2);
+    }
+    if let Some(poll_interval_ms) = cli.poll_interval_ms {
+        config.poll_interval_ms = poll_interval_ms;
+    }
+    // `search` and `get` only print, so they load like `view` and can't touch the files.
+    if cli.read_only || matches!(cli.command.as_deref(), Some("view" | "search" | "get")) {
+        config.read_only = true;
+    }
+    if config.read_only || cli.command.as_deref() == Some("pick") {
+        // The usage log is a write like any other, and `clipb pick` prints its entry rather
+        // than copying it.
+        config.log_usage = false;
+    }
+    // Named histories are switched between with Tab; without any, the default file is used.
+    let tabs: Vec<Option<&str>> = if config.histories.is_empty() {
+        vec![None]
+    } else {
+        config
+            .histories
+            .iter()
+            .map(|name| Some(name.as_str()))
+            .collect()
+    };
+
+    match cli.command.as_deref() {
+        None | Some("pick" | "view") => {}
+        Some("prune") => {
+            let Some(older_than) = cli.older_than else {
+                eprintln!("clipb: prune requires --older-than <duration>");
+                std::process::exit(2);
+            };
+            if config.read_only {
+                eprintln!("clipb: prune writes to the history, which read_only forbids");
+                std::process::exit(2);
+            }
+            if config.confirm_destructive && !confirm("Delete entries older than the cutoff?")? {
+                return Ok(());
+            }
+            match prune_older_than(&tabs, older_than, &config) {
+                Ok(removed) => println!("Removed {} entries", removed),
+                Err(e) => {
+                    eprintln!("clipb: {}", e);
+                    std::process::exit(2);
+                }
+            }
+            return Ok(());
+        }
+        Some("search") => {
+            let query = cli.operands.join(" ");
+            if query.trim().is_empty() {
+                eprintln!("clipb: search requires a query");
+                std::process::exit(2);
+            }
+            let hist = ClipHistory::load(&config, tabs[0]);
+            let hist = limit_entries(hist.entries(), cli.max_entries);
+            if !print_matches(&hist, &query, cli.search_options) {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some("get") => {
+            let index = match cli.operands.as_slice() {
+                [n] => parse_positive::<usize>("get", Some(n.clone())),
+                _ => Err("get requires one entry number".to_string()),
+            };
+            let index = index.unwrap_or_else(|e| {
+                eprintln!("clipb: {}", e);
+                std::process::exit(2);
+            });
+            let hist = ClipHistory::load(&config, tabs[0]);
+            let hist = limit_entries(hist.entries(), cli.max_entries);
+            let Some(entry) = hist.iter().rev().nth(index - 1) else {
+                eprintln!("clipb: no entry {}", index);
+                std::process::exit(1);
+            };
+            let mut stdout = io::stdout();
+            stdout.write_all(entry.content.as_bytes())?;
+            stdout.flush()?;
+            return Ok(());
+        }
+        Some(other) => {
+            eprintln!("clipb: unknown command '{}'", other);
+            std::process::exit(2);
+        }
+    }
+
+    let mut active_tab = 0;
+    let mut snippets = load_snippets();
+    let history = Arc::new(Mutex::new(ClipHistory::load(&config, tabs[active_tab])));
+    let history_clone = Arc::clone(&history);
+    // What the poll thread reports back: captures, the live clipboard and connection trouble.
+    let capture = Arc::new(Capture::default());
+    let capture_clone = Arc::clone(&capture);
+    let mut input_mode = match cli.search.clone() {
+        Some(query) => InputMode::Searching(query),
+        None => InputMode::Normal,
+    };
+    let mut view: Option<View> = None;
+    // Previous search queries, oldest first, and the one currently recalled into the buffer.
+    let mut search_history: Vec<String> = Vec::new();
+    let mut search_recall: Option<usize> = None;
+    // The entry under the cursor and the scroll offset when a search began, for `Esc` to go
+    // back to.
+    let mut pre_search: Option<(Option<u64>, usize)> = None;
+    // Kept between searches, like the query history.
+    let mut search_options = SearchOptions::default();
+    // Cycled with `S`; the last choice is kept for the next run.
+    let mut ui_state = load_ui_state();
+    let mut sort = ui_state.sort.unwrap_or(config.sort);
+    // Toggled with `m`: just the list, without preview, footer or current-clipboard line.
+    let mut compact = config.compact;
+    // One-off message shown in the footer until the next key press.
+    let mut status: Option<String> = None;
+    // `clipb pick` prints the chosen entry to stdout, so the TUI draws on stderr instead.
+    let pick_mode = cli.command.as_deref() == Some("pick");
+    let mut picked: Option<String> = None;
 
-    if path.exists() {
-        let data = fs::read_to_string(&path).unwrap();
-        serde_json::from_str(&data).unwrap_or(Config {
-            max_history: 200,
-            poll_interval_ms: 300,
-        })
-    } else {
-        Config {
-            max_history: 200,
-            poll_interval_ms: 300,
+    // The local API shares the history with the UI and lives as long as it does.
+    if config.server_port != 0 && !pick_mode {
+        match server::bind(config.server_port) {
+            Ok(listener) => {
+                let history = Arc::clone(&history);
+                let capture = Arc::clone(&capture);
+                let server_config = config.clone();
+                let token = config.server_token.clone().unwrap_or_default();
+                spawn(move || {
+                    server::serve(listener, history, server_config, token, || {
+                        capture.captured.fetch_add(1, Ordering::Relaxed);
+                    })
+                });
+            }
+            Err(e) => {
+                status = Some(format!(
+                    "API not started on port {}: {}",
+                    config.server_port, e
+                ));
+            }
         }
     }
-}
 
-fn get_history_path() -> PathBuf {
-    let mut path = config_dir().unwrap_or_else(|| PathBuf::from("."));
-    path.push("clipman");
-    fs::create_dir_all(&path).unwrap();
-    path.push("history.json");
-    path
-}
+    // Failures on other threads (the poll thread, paste/open commands) go to the footer
+    // through here; writing them to the terminal would tear through the UI.
+    let (background_errors_tx, background_errors) = mpsc::channel::<String>();
+    let poll_errors = background_errors_tx.clone();
+    let poll_config = config.clone();
+    let poll = move || {
+        capture::run(
+            &capture_clone,
+            &history_clone,
+            &poll_config,
+            SystemClipboard::new,
+            |e| {
+                let _ = poll_errors.send(e);
+            },
+        )
+    };
+    // Nothing is captured in read-only mode.
+    let poller = (!config.read_only).then(|| spawn(poll));
 
-fn load_history() -> Vec<Entry> {
-    let path = get_history_path();
-    if path.exists() {
-        let data = fs::read_to_string(path).unwrap_or_default();
-        serde_json::from_str(&data).unwrap_or_default()
-    } else {
-        Vec::new()
+    // SIGTERM/SIGHUP (and SIGINT when it isn't swallowed by raw mode) take the same exit path as
+    // 'q' so the terminal is restored and history is saved.
+    let shutdown = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM] {
+        signal_hook::flag::register(signal, Arc::clone(&shutdown))?;
     }
-}
-
-fn save_history(history: &Vec<Entry>) {
-    let path = get_history_path();
-    let data = serde_json::to_string_pretty(history).unwrap();
-    fs::write(path, data).unwrap();
-}
+    #[cfg(unix)]
+    signal_hook::flag::register(signal_hook::consts::SIGHUP, Arc::clone(&shutdown))?;
 
-fn to_list_item(e: &Entry) -> ListItem<'_> {
-    let display = if e.content.trim().is_empty() {
-        " ".to_string()
+    enable_raw_mode()?;
+    let mut out: Box<dyn Write> = if pick_mode {
+        Box::new(io::stderr())
     } else {
-        e.content.clone()
+        Box::new(io::stdout())
     };
-    ListItem::new(format!("[{}] {}", e.timestamp, display))
-}
-
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let history = Arc::new(Mutex::new(load_history()));
-    let history_clone = Arc::clone(&history);
-    let config = load_config();
-    let mut input_mode = InputMode::Normal;
+    // Without the alternate screen the UI takes a band at the bottom of the normal buffer and
+    // what it last showed stays in the scrollback.
+    let viewport = if config.alternate_screen {
+        execute!(out, EnterAlternateScreen)?;
+        Viewport::Fullscreen
+    } else {
+        Viewport::Inline(INLINE_HEIGHT)
+    };
+    let backend = CrosstermBackend::new(out);
+    let mut terminal = Terminal::with_options(backend, TerminalOptions { viewport })?;
 
-    spawn(move || {
-        let mut clipboard = Clipboard::new().unwrap();
-        let mut last_text: Option<String> = None;
+    let theme = Theme::new(color_enabled(&config));
+    let mut list_state = ListState::default();
+    {
+        let shared = limit_entries(history.lock().unwrap().entries(), cli.max_entries);
+        let entries = visible_entries(&shared, &input_mode, search_options, sort, &config);
+        let initial = match config.initial_selection {
+            InitialSelection::Newest => 0,
+            InitialSelection::Oldest => entries.len().saturating_sub(1),
+            InitialSelection::LastViewed => {
+                let viewed = ui_state
+                    .last_viewed
+                    .as_ref()
+                    .and_then(|viewed| entries.iter().position(|e| ViewedEntry::of(e) == *viewed));
+                if let Some(idx) = viewed {
+                    let row = display_row(&entries, idx, sort, &config);
+                    *list_state.offset_mut() = row.saturating_sub(ui_state.last_viewed_row);
+                }
+                viewed.unwrap_or(0)
+            }
+        };
+        list_state.select(Some(initial));
+    }
+    // Rows between the top of the list and the selection in the last frame, to keep the
+    // selection where it is on screen when the list under it changes.
+    let mut cursor_row = 0;
+    // Captures the user has seen.
+    let mut seen_captures = 0;
 
-        loop {
-            match clipboard.get_text() {
-                Ok(current_text) => {
-                    let is_only_newline = current_text.chars().all(|c| c == '\n' || c == '\r');
+    // Frames are only drawn when something visible changed, and the history is only cloned when
+    // it may have; the once-a-second clock tick redraws from the cached copy.
+    let mut hist: Vec<Entry> = Vec::new();
+    let mut total_captures = 0;
+    let mut hist_stale = true;
+    let mut live_text: Option<String> = None;
+    let mut notice_text: Option<String> = None;
+    let mut clock = String::new();
+    let mut needs_redraw = true;
+    // Thumbnails live outside ratatui's buffer, so track which one is on screen and where.
+    let graphics = graphics_protocol();
+    let mut shown_thumbnail: Option<(String, Rect)> = None;
+    let command_timeout = Duration::from_millis(config.command_timeout_ms);
+    let idle_timeout = Duration::from_secs(config.idle_timeout_secs);
+    let mut last_key = Instant::now();
 
-                    if is_only_newline {
-                        thread::sleep(Duration::from_millis(config.poll_interval_ms));
-                        continue;
-                    }
+    loop {
+        if shutdown.load(Ordering::Relaxed) {
+            break;
+        }
+        if config.idle_timeout_secs > 0 && last_key.elapsed() >= idle_timeout {
+            break;
+        }
+        if let Some(e) = background_errors.try_iter().last() {
+            status = Some(e);
+            needs_redraw = true;
+        }
 
-                    if last_text.as_ref() != Some(&current_text) {
-                        let timestamp = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
-                        last_text = Some(current_text.clone());
+        if hist_stale || capture.captured.load(Ordering::Relaxed) != total_captures {
+            // Keep the cursor on the same entry as new clips arrive, wherever they land in the
+            // list, unless it is following the newest entry at the top.
+            let arrived = capture.captured.load(Ordering::Relaxed) != total_captures;
+            let anchor = match list_state.selected() {
+                Some(idx)
+                    if arrived
+                        && matches!(input_mode, InputMode::Normal)
+                        && (idx > 0 || sort != SortOrder::Recent) =>
+                {
+                    visible_entries(&hist, &input_mode, search_options, sort, &config)
+                        .get(idx)
+                        .map(|e| e.id)
+                }
+                _ => None,
+            };
+            let mut shared = history.lock().unwrap();
+            hist = limit_entries(shared.entries(), cli.max_entries);
+            let capped = shared.take_capped();
+            if capped > 0 {
+                status = Some(format!(
+                    "History file reached max_file_bytes: dropped the {} oldest entries",
+                    capped
+                ));
+            }
+            if let Some(e) = shared.take_write_error() {
+                status = Some(e);
+            }
+            total_captures = capture.captured.load(Ordering::Relaxed);
+            hist_stale = false;
+            needs_redraw = true;
+            drop(shared);
+            if let Some(id) = anchor
+                && let Some(idx) =
+                    visible_entries(&hist, &input_mode, search_options, sort, &config)
+                        .iter()
+                        .position(|e| e.id == id)
+            {
+                list_state.select(Some(idx));
+            }
+        }
+        {
+            let live_now = capture.live.lock().unwrap();
+            if *live_now != live_text {
+                live_text = live_now.clone();
+                needs_redraw = true;
+            }
+            let notice_now = capture.notice.lock().unwrap();
+            if *notice_now != notice_text {
+                notice_text = notice_now.clone();
+                needs_redraw = true;
+            }
+        }
+        let now = Local::now().format("%H:%M:%S").to_string();
+        if now != clock {
+            clock = now;
+            needs_redraw = true;
+        }
 
-                        std::io::stdout().flush().unwrap();
+        let unseen = total_captures - seen_captures;
 
-                        let mut hist = history_clone.lock().unwrap();
-                        hist.push(Entry {
-                            timestamp,
-                            content: current_text.clone(),
-                        });
+        if needs_redraw {
+            // The entry under the cursor is never the one pruned to make room for a new clip.
+            let selected_entry = list_state
+                .selected()
+                .and_then(|idx| {
+                    visible_entries(&hist, &input_mode, search_options, sort, &config)
+                        .get(idx)
+                        .copied()
+                })
+                .map(|e| e.id);
+            history.lock().unwrap().protect(selected_entry);
 
-                        let max_history = config.max_history;
+            let thumbnail = graphics.and_then(|_| {
+                if view.is_some() || matches!(input_mode, InputMode::Snippets(_)) {
+                    return None;
+                }
+                let frame = terminal.get_frame().area();
+                if frame.width < MIN_WIDTH || frame.height < MIN_HEIGHT {
+                    return None;
+                }
+                let preview = compute_layout(frame, compact).preview?;
+                let name = list_state
+                    .selected()
+                    .and_then(|idx| {
+                        visible_entries(&hist, &input_mode, search_options, sort, &config)
+                            .get(idx)
+                            .copied()
+                    })?
+                    .image
+                    .clone()?;
+                // Below the placeholder line, inside the border.
+                let area = Rect::new(
+                    preview.x + 1,
+                    preview.y + 2,
+                    preview.width.saturating_sub(2),
+                    preview.height.saturating_sub(3),
+                );
+                (!area.is_empty()).then_some((name, area))
+            });
+            if thumbnail != shown_thumbnail && shown_thumbnail.is_some() {
+                match graphics {
+                    Some(Graphics::Kitty) => {
+                        write!(terminal.backend_mut(), "\x1b_Ga=d,q=2\x1b\\")?;
+                    }
+                    // Sixel pixels are part of the screen; only a full repaint removes them.
+                    _ => terminal.clear()?,
+                }
+            }
 
-                        if hist.len() > max_history {
-                            hist.remove(0);
+            terminal.draw(|f| {
+                // Below this nothing useful fits; say so instead of squeezing the layout to
+                // nothing.
+                if f.area().width < MIN_WIDTH || f.area().height < MIN_HEIGHT {
+                    let message = Paragraph::new("Terminal too small").wrap(Wrap { trim: true });
+                    f.render_widget(message, f.area());
+                    return;
+                }
+                let layout = compute_layout(f.area(), compact);
+                let name = match tabs[active_tab] {
+                    Some(tab) => format!(
+                        "Clipboard History [{}/{}: {}]",
+                        active_tab + 1,
+                        tabs.len(),
+                        tab
+                    ),
+                    None => "Clipboard History".to_string(),
+                };
+                let name = match sort {
+                    SortOrder::Recent => name,
+                    SortOrder::Alphabetical => format!("{}, A to Z", name),
+                    SortOrder::Usage => format!("{}, most used first", name),
+                    SortOrder::Length => format!("{}, longest first", name),
+                };
+                let name = if config.read_only {
+                    format!("{} [read-only]", name)
+                } else {
+                    name
+                };
+                let title = match &input_mode {
+                    InputMode::Normal if unseen > 0 => {
+                        format!("{} ({} items) (+{} new)", name, hist.len(), unseen)
+                    }
+                    InputMode::Normal | InputMode::Confirm(_) | InputMode::Quoting { .. } => {
+                        format!("{} ({} items)", name, hist.len())
+                    }
+                    InputMode::Searching(query) => {
+                        let flags: Vec<&str> = [
+                            (search_options.whole_word, "whole word"),
+                            (search_options.collapse_whitespace, "any spacing"),
+                        ]
+                        .into_iter()
+                        .filter_map(|(on, flag)| on.then_some(flag))
+                        .collect();
+                        if flags.is_empty() {
+                            format!("Search: {}", query)
+                        } else {
+                            format!("Search [{}]: {}", flags.join(", "), query)
                         }
+                    }
+                    InputMode::Snippets(None | Some(SnippetEdit::Deleting { .. })) => {
+                        format!("Snippets ({} items)", snippets.len())
+                    }
+                    InputMode::Snippets(Some(SnippetEdit::Naming(draft))) => {
+                        format!("Snippet name: {}", draft.name)
+                    }
+                    InputMode::Snippets(Some(SnippetEdit::Writing(draft))) => format!(
+                        "Snippet {}: {}",
+                        if draft.name.is_empty() { "content" } else { draft.name.as_str() },
+                        draft.content.lines().last().unwrap_or("")
+                    ),
+                    InputMode::Labeling { buffer, .. } => format!("Label: {}", buffer),
+                    InputMode::Saving { buffer, .. } => format!("Save to: {}", buffer),
+                    InputMode::Noting { buffer, .. } => format!("Note: {}", buffer),
+                };
 
-                        save_history(&hist);
+                let (visible, unlisted) =
+                    listed_entries(&hist, &input_mode, search_options, sort, &config);
+                let selected = list_state.selected();
+                let (len, mut preview_text, note, usage) = match &input_mode {
+                    InputMode::Snippets(edit) => (
+                        snippets.len(),
+                        match edit {
+                            Some(SnippetEdit::Naming(draft) | SnippetEdit::Writing(draft)) => {
+                                draft.content.as_str()
+                            }
+                            _ => selected
+                                .and_then(|idx| snippets.get(idx))
+                                .map_or("", |s| s.content.as_str()),
+                        },
+                        None,
+                        None,
+                    ),
+                    _ => {
+                        let entry = selected.and_then(|idx| visible.get(idx));
+                        (
+                            visible.len(),
+                            entry.map_or("", |e| e.content.as_str()),
+                            entry.and_then(|e| e.note.as_deref()),
+                            entry.and_then(|e| usage_summary(e)),
+                        )
+                    }
+                };
+                // While searching, the preview starts a few lines above the first match so it isn't
+                // buried further down a long clip.
+                let mut preview_title = "Preview".to_string();
+                if config.similarity_hint
+                    && !matches!(input_mode, InputMode::Snippets(_))
+                    && let Some(idx) = selected
+                    && let Some((pct, neighbor)) = similar_neighbor(&visible, idx)
+                {
+                    preview_title = format!("Preview — {}% similar to #{}", pct, neighbor + 1);
+                }
+                let search = match &input_mode {
+                    InputMode::Searching(query) => Some(Query::parse(query, search_options)),
+                    _ => None,
+                };
+                // Only the part the preview can show is searched, as this runs every frame.
+                let scanned = truncate_bytes(preview_text, config.preview_max_bytes);
+                if let Some(line) = search.as_ref().and_then(|q| q.match_line(scanned)) {
+                    let skipped = line.saturating_sub(config.match_context_lines);
+                    if skipped > 0 {
+                        preview_text = skip_lines(preview_text, skipped);
+                        preview_title = format!("Preview (from line {})", skipped + 1);
                     }
                 }
-                Err(e) => eprintln!("Error accessing clipboard: {}", e),
-            }
-            thread::sleep(Duration::from_millis(config.poll_interval_ms));
-        }
-    });
 
-    enable_raw_mode()?;
-    let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen)?;
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend)?;
+                // Only the rows that fit become `ListItem`s, so a frame costs the same however long
+                // the history is; the window is rendered with a selection relative to its start.
+                // With `group_by_date` the window and offset count header rows too, while the
+                // selection stays an index into `visible`.
+                let rows = layout.list.height.saturating_sub(2) as usize;
+                let grouped = (config.group_by_date
+                    && sort == SortOrder::Recent
+                    && !matches!(input_mode, InputMode::Snippets(_)))
+                .then(|| date_rows(&visible));
+                let (row_count, selected_row) = match &grouped {
+                    Some(list_rows) => (
+                        list_rows.len(),
+                        selected.map(|idx| {
+                            let is_selected =
+                                |row: &ListRow| matches!(row, ListRow::Entry(i) if *i == idx);
+                            list_rows.iter().position(is_selected).unwrap_or(0)
+                        }),
+                    ),
+                    None => (len, selected),
+                };
+                let mut offset =
+                    scroll_offset(list_state.offset(), selected_row.unwrap_or(0), row_count, rows);
+                // The first entry of a day brings its header into view with it.
+                if let (Some(list_rows), Some(row)) = (&grouped, selected_row)
+                    && rows > 1
+                    && offset > 0
+                    && offset == row
+                    && matches!(list_rows[offset - 1], ListRow::Header(_))
+                {
+                    offset -= 1;
+                }
+                *list_state.offset_mut() = offset;
+                cursor_row = selected_row.unwrap_or(0).saturating_sub(offset);
+                let window = offset..(offset + rows).min(row_count);
+                let columns = config.list_columns && layout.list.width >= COLUMNS_MIN_WIDTH;
+                let index_width = len.to_string().len();
+                let entry_item = |e, index: usize| {
+                    let key = quick_copy_key(index, &config);
+                    if columns {
+                        to_column_item(e, index, index_width, key, search.as_ref(), &theme)
+                    } else {
+                        let template = &config.list_template;
+                        to_list_item(e, index, template, key, search.as_ref(), &theme)
+                    }
+                };
+                let items: Vec<ListItem> = match (&input_mode, &grouped) {
+                    (InputMode::Snippets(_), _) => snippets[window]
+                        .iter()
+                        .map(|s| snippet_list_item(s, &theme))
+                        .collect(),
+                    (_, Some(list_rows)) => list_rows[window]
+                        .iter()
+                        .map(|row| match row {
+                            ListRow::Header(day) => {
+                                ListItem::new(Span::styled(format!("── {}", day), theme.label))
+                            }
+                            ListRow::Entry(idx) => entry_item(visible[*idx], idx + 1),
+                        })
+                        .collect(),
+                    (_, None) => visible[window.clone()]
+                        .iter()
+                        .zip(window.start + 1..)
+                        .map(|(e, index)| entry_item(*e, index))
+                        .collect(),
+                };
+                let mut window_state = ListState::default()
+                    .with_selected(selected_row.map(|row| row.saturating_sub(offset)));
 
-    let mut list_state = ListState::default();
-    list_state.select(Some(0));
+                let mut block = Block::default().borders(Borders::ALL).title(title);
+                if unlisted > 0 {
+                    let more = format!(" …and {} more, refine your search ", unlisted);
+                    block = block.title_bottom(Line::from(Span::styled(more, theme.meta)));
+                }
+                if items.is_empty() {
+                    let message = match &input_mode {
+                        InputMode::Searching(query) if query.is_empty() => {
+                            "Type to search".to_string()
+                        }
+                        InputMode::Searching(query) => format!("No matches for '{}'", query),
+                        InputMode::Snippets(_) if config.read_only => {
+                            "No snippets yet — add some to snippets.json".to_string()
+                        }
+                        InputMode::Snippets(_) => {
+                            "No snippets yet — press a to add one".to_string()
+                        }
+                        _ => config.empty_message.clone(),
+                    };
+                    let inner = block.inner(layout.list);
+                    f.render_widget(block, layout.list);
+                    let [_, middle, _] = Layout::vertical([
+                        Constraint::Fill(1),
+                        Constraint::Length(1),
+                        Constraint::Fill(1),
+                    ])
+                    .areas(inner);
+                    f.render_widget(Paragraph::new(message).style(theme.meta).centered(), middle);
+                } else {
+                    let list = List::new(items)
+                        .block(block)
+                        .highlight_style(theme.highlight)
+                        .highlight_symbol(">>");
+                    f.render_stateful_widget(list, layout.list, &mut window_state);
+                }
 
-    loop {
-        let hist = history.lock().unwrap().clone();
+                if let Some(area) = layout.live {
+                    let current = live_text
+                        .as_deref()
+                        .and_then(|t| t.lines().next())
+                        .unwrap_or("");
+                    let line = Line::from(vec![
+                        Span::styled(
+                            " (current clipboard) ",
+                            theme.live_label,
+                        ),
+                        Span::styled(
+                            format!(" {}", current),
+                            theme.live_text,
+                        ),
+                    ]);
+                    f.render_widget(Paragraph::new(line), area);
+                }
 
-        terminal.draw(|f| {
-            let size = f.area();
-            let title = match &input_mode {
-                InputMode::Normal => format!("Clipboard History ({} items)", hist.len()),
-                InputMode::Searching(query) => format!("Search: {}", query),
-            };
+                if let Some(mut area) = layout.preview {
+                    // The note sits under the content, a few lines at most.
+                    if let Some(note) = note {
+                        let width = area.width.saturating_sub(2).max(1) as usize;
+                        let lines = note.chars().count().div_ceil(width).clamp(1, NOTE_MAX_LINES);
+                        let [content_area, note_area] = Layout::vertical([
+                            Constraint::Min(0),
+                            Constraint::Length(lines as u16 + 2),
+                        ])
+                        .areas(area);
+                        let note = Paragraph::new(note)
+                            .wrap(Wrap { trim: false })
+                            .style(theme.label)
+                            .block(Block::default().borders(Borders::ALL).title("Note"));
+                        f.render_widget(note, note_area);
+                        area = content_area;
+                    }
+                    let mut block = Block::default().borders(Borders::ALL).title(preview_title);
+                    if let Some(usage) = usage {
+                        block = block
+                            .title_bottom(Line::from(format!(" {} ", usage)).right_aligned());
+                    }
+                    let preview = Paragraph::new(preview_content(
+                        preview_text,
+                        config.preview_max_bytes,
+                        config.preview_max_lines,
+                        area.width.saturating_sub(2) as usize,
+                    ))
+                        .wrap(Wrap { trim: false })
+                        .block(block);
+                    f.render_widget(preview, area);
+                }
+
+                if let Some(area) = layout.footer {
+                    let hints = match status.as_ref().or(notice_text.as_ref()) {
+                        Some(message) => message.as_str(),
+                        None => match &input_mode {
+                            InputMode::Normal if tabs.len() > 1 => {
+                                "↑/↓ navigate  Enter copy  c copy plain  P paste  v view  l label  / search  s snippets  Tab history  ? help  q quit"
+                            }
+                            InputMode::Normal => {
+                                "↑/↓ navigate  Enter copy  c copy plain  P paste  v view  l label  / search  s snippets  ? help  q quit"
+                            }
+                            InputMode::Searching(_) => {
+                                "↑/↓ navigate  Enter copy  Ctrl+P/N previous searches  Ctrl+W whole word  Ctrl+S any spacing  Esc cancel"
+                            }
+                            InputMode::Confirm(action) => action.prompt(),
+                            InputMode::Quoting { .. } => {
+                                "Copy quoted: s for a shell  m as a Markdown code block  Esc cancel"
+                            }
+                            InputMode::Snippets(None) if config.read_only => {
+                                "↑/↓ navigate  Enter copy  Esc back"
+                            }
+                            InputMode::Snippets(None) => {
+                                "↑/↓ navigate  Enter copy  a add  e edit  x delete  Esc back"
+                            }
+                            InputMode::Snippets(Some(SnippetEdit::Naming(_))) => {
+                                "Enter next: the content (name optional)  Esc cancel"
+                            }
+                            InputMode::Snippets(Some(SnippetEdit::Writing(_))) => {
+                                "Enter save  Ctrl+J new line  Esc cancel"
+                            }
+                            InputMode::Snippets(Some(SnippetEdit::Deleting { .. })) => {
+                                "Delete this snippet? y/n"
+                            }
+                            InputMode::Labeling { .. } | InputMode::Noting { .. } => {
+                                "Enter save (empty clears)  Esc cancel"
+                            }
+                            InputMode::Saving { .. } => {
+                                "Enter write the entry to this file (~ is your home)  Esc cancel"
+                            }
+                        },
+                    };
+                    let [hints_area, clock_area] = Layout::horizontal([
+                        Constraint::Min(0),
+                        Constraint::Length(clock.len() as u16),
+                    ])
+                    .areas(area);
+                    f.render_widget(Paragraph::new(hints), hints_area);
+                    f.render_widget(Paragraph::new(clock.as_str()), clock_area);
+                }
 
-            let items: Vec<ListItem> = match &input_mode {
-                InputMode::Normal => hist.iter().rev().map(|e| to_list_item(e)).collect(),
+                if let Some(view) = &view {
+                    let area = f.area();
+                    let height = area.height.saturating_sub(2) as usize;
+                    let text: Vec<&str> =
+                        view.content.lines().skip(view.scroll).take(height).collect();
+                    let paragraph = Paragraph::new(text.join("\n"))
+                        .wrap(Wrap { trim: false })
+                        .block(
+                            Block::default()
+                                .borders(Borders::ALL)
+                                .title(format!("{} (↑/↓ scroll, Esc close)", view.title)),
+                        );
+                    f.render_widget(Clear, area);
+                    f.render_widget(paragraph, area);
+                }
+            })?;
+            if thumbnail != shown_thumbnail
+                && let (Some(graphics), Some((name, area))) = (graphics, &thumbnail)
+            {
+                draw_thumbnail(terminal.backend_mut(), graphics, name, *area)?;
+            }
+            shown_thumbnail = thumbnail;
+            needs_redraw = false;
+        }
 
-                InputMode::Searching(query) => {
-                    if query.is_empty() {
-                        Vec::new()
-                    } else {
-                        hist.iter()
-                            .rev()
-                            .filter(|e| e.content.to_lowercase().contains(&query.to_lowercase()))
-                            .map(|e| to_list_item(e))
-                            .collect()
+        if event::poll(Duration::from_millis(200))? {
+            // Any input may change what is shown, and key handlers may edit the history.
+            needs_redraw = true;
+            hist_stale = true;
+            let key = match event::read()? {
+                CEvent::Key(key) => key,
+                CEvent::Resize(_, _) => {
+                    // Repaint from scratch so widgets from the old size don't linger.
+                    terminal.autoresize()?;
+                    terminal.clear()?;
+                    if shown_thumbnail.take().is_some() && graphics == Some(Graphics::Kitty) {
+                        write!(terminal.backend_mut(), "\x1b_Ga=d,q=2\x1b\\")?;
                     }
+                    continue;
                 }
+                _ => continue,
             };
+            seen_captures = total_captures;
+            status = None;
+            last_key = Instant::now();
 
-            let list = List::new(items)
-                .block(Block::default().borders(Borders::ALL).title(title))
-                .highlight_symbol(">>");
+            // Raw mode turns Ctrl+C into a key press instead of SIGINT.
+            if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+                break;
+            }
 
-            f.render_stateful_widget(list, size, &mut list_state);
-        })?;
+            if let Some(v) = &mut view {
+                let last_line = v.content.lines().count().saturating_sub(1);
+                match key.code {
+                    KeyCode::Esc | KeyCode::Char('q' | 'v' | '?') => view = None,
+                    KeyCode::Down => v.scroll = (v.scroll + 1).min(last_line),
+                    KeyCode::Up => v.scroll = v.scroll.saturating_sub(1),
+                    KeyCode::PageDown => v.scroll = (v.scroll + 20).min(last_line),
+                    KeyCode::PageUp => v.scroll = v.scroll.saturating_sub(20),
+                    KeyCode::Home => v.scroll = 0,
+                    KeyCode::End => v.scroll = last_line,
+                    _ => {}
+                }
+                continue;
+            }
 
-        if event::poll(Duration::from_millis(200))? {
-            if let CEvent::Key(key) = event::read()? {
-                match &input_mode {
-                    InputMode::Normal => {
-                        let len = history.lock().unwrap().len();
-                        let mut selected = list_state.selected().unwrap_or(0);
-
-                        match key.code {
-                            KeyCode::Char('q') => break,
-                            KeyCode::Down => {
-                                if selected + 1 < len {
-                                    selected += 1;
+            let visible = visible_entries(&hist, &input_mode, search_options, sort, &config);
+            let len = visible.len();
+
+            if let InputMode::Normal = &input_mode {
+                let mut selected = list_state.selected().unwrap_or(0);
+
+                match key.code {
+                    KeyCode::Char('q') => break,
+                    KeyCode::Esc if pick_mode => break,
+                    KeyCode::Char('p' | 't' | 'B' | 'n' | 'l' | 'x' | 'X') if config.read_only => {
+                        status = Some("Read-only: the history can't be changed".to_string());
+                    }
+                    KeyCode::Down => {
+                        if selected + 1 < len {
+                            selected += 1;
+                        }
+                        list_state.select(Some(selected));
+                    }
+                    KeyCode::Up => {
+                        selected = selected.saturating_sub(1);
+                        list_state.select(Some(selected));
+                    }
+                    // With `quick_copy`, 1-9 pick one of the top nine rows as if it were selected.
+                    KeyCode::Enter | KeyCode::Char('1'..='9')
+                        if key.code == KeyCode::Enter || config.quick_copy =>
+                    {
+                        let idx = match key.code {
+                            KeyCode::Char(digit) => digit.to_digit(10).map(|d| d as usize - 1),
+                            _ => list_state.selected(),
+                        };
+                        if let Some(idx) = idx
+                            && let Some(entry) = visible.get(idx)
+                        {
+                            list_state.select(Some(idx));
+                            if !pick_mode
+                                && let Err(e) = copy_entry(entry, &config, &capture.restored)
+                            {
+                                status = Some(format!("Copy failed: {}", e));
+                            } else {
+                                status = after_copy(&history, entry, "copy", &config);
+                                if pick_mode || config.exit_on_select {
+                                    picked = Some(entry.content.clone());
+                                    break;
                                 }
-                                list_state.select(Some(selected));
                             }
-                            KeyCode::Up => {
-                                if selected > 0 {
-                                    selected -= 1;
+                        }
+                    }
+                    KeyCode::Char('/') => {
+                        let selected = list_state.selected().and_then(|idx| visible.get(idx));
+                        pre_search = Some((selected.map(|e| e.id), list_state.offset()));
+                        input_mode = InputMode::Searching(String::new());
+                        search_recall = None;
+                        list_state.select(Some(0));
+                        continue;
+                    }
+                    // Search for what is on the clipboard now; its first line is enough to go on
+                    // and keeps the query editable.
+                    KeyCode::Char('f') => {
+                        // Nothing polls the clipboard in read-only mode, so it is read here.
+                        let clipboard_text = if config.read_only {
+                            SystemClipboard::new()
+                                .ok()
+                                .and_then(|mut clipboard| clipboard.get_text().ok())
+                        } else {
+                            live_text.clone()
+                        };
+                        let seed: String = clipboard_text
+                            .as_deref()
+                            .and_then(|text| text.lines().find(|line| !line.trim().is_empty()))
+                            .map(|line| line.trim().chars().take(SEED_MAX_CHARS).collect())
+                            .unwrap_or_default();
+                        if seed.is_empty() {
+                            status = Some("Nothing on the clipboard to search for".to_string());
+                        } else {
+                            let selected = list_state.selected().and_then(|idx| visible.get(idx));
+                            pre_search = Some((selected.map(|e| e.id), list_state.offset()));
+                            input_mode = InputMode::Searching(seed);
+                            search_recall = None;
+                            list_state.select(Some(0));
+                            continue;
+                        }
+                    }
+                    KeyCode::Char('i') => view = Some(info_view(&history.lock().unwrap())),
+                    KeyCode::Char('m') => {
+                        compact = !compact;
+                        terminal.clear()?;
+                    }
+                    KeyCode::Char('S') => {
+                        sort = match sort {
+                            SortOrder::Recent => SortOrder::Alphabetical,
+                            SortOrder::Alphabetical => SortOrder::Usage,
+                            SortOrder::Usage => SortOrder::Length,
+                            SortOrder::Length => SortOrder::Recent,
+                        };
+                        ui_state.sort = Some(sort);
+                        if !config.read_only {
+                            save_ui_state(&ui_state);
+                        }
+                        list_state.select(Some(0));
+                    }
+                    KeyCode::Char('?') => {
+                        view = Some(View {
+                            title: "Help".to_string(),
+                            content: HELP.to_string(),
+                            scroll: 0,
+                        });
+                    }
+                    KeyCode::Char('v') => {
+                        if let Some(entry) = list_state.selected().and_then(|idx| visible.get(idx))
+                        {
+                            view = Some(View {
+                                title: format!("[{}]", display_timestamp(&entry.timestamp)),
+                                content: entry.content.clone(),
+                                scroll: 0,
+                            });
+                        }
+                    }
+                    KeyCode::Char('b') => {
+                        if let Some(entry) = list_state.selected().and_then(|idx| visible.get(idx))
+                        {
+                            view = Some(View {
+                                title: format!("Bytes ({})", format_size(entry.content.len())),
+                                content: hex_dump(entry.content.as_bytes()),
+                                scroll: 0,
+                            });
+                        }
+                    }
+                    KeyCode::Char('d') => {
+                        if let Some(entry) = list_state.selected().and_then(|idx| visible.get(idx))
+                        {
+                            match decode_view(&entry.content) {
+                                Ok(decoded) => view = Some(decoded),
+                                Err(e) => status = Some(e),
+                            }
+                        }
+                    }
+                    KeyCode::Char('s') => {
+                        input_mode = InputMode::Snippets(None);
+                        list_state.select(Some(0));
+                        continue;
+                    }
+                    KeyCode::Char('p') => {
+                        if let Some(entry) = list_state.selected().and_then(|idx| visible.get(idx))
+                        {
+                            let mut hist = history.lock().unwrap();
+                            if let Some(target) = hist.find_mut(entry.id) {
+                                target.pinned = !target.pinned;
+                                hist.save();
+                                hist.save_pinned();
+                            }
+                        }
+                    }
+                    KeyCode::Char('t') => {
+                        if let Some(entry) = list_state.selected().and_then(|idx| visible.get(idx))
+                        {
+                            let now = now_timestamp(config.timestamp_utc);
+                            history.lock().unwrap().promote(entry.id, now);
+                            list_state.select(Some(0));
+                        }
+                    }
+                    KeyCode::Char('B') => {
+                        if let Some(entry) = list_state.selected().and_then(|idx| visible.get(idx))
+                        {
+                            let mut hist = history.lock().unwrap();
+                            if let Some(target) = hist.find_mut(entry.id) {
+                                target.burn = !target.burn;
+                                hist.save();
+                            }
+                        }
+                    }
+                    KeyCode::Char('c') if !pick_mode => {
+                        if let Some(entry) = list_state.selected().and_then(|idx| visible.get(idx))
+                        {
+                            status =
+                                match set_clipboard(&strip_ansi(&entry.content), &capture.restored)
+                                {
+                                    Ok(()) => after_copy(&history, entry, "copy_plain", &config),
+                                    Err(e) => Some(format!("Copy failed: {}", e)),
+                                };
+                        }
+                    }
+                    KeyCode::Char('o') | KeyCode::Char('O') => {
+                        let Some(entry) = list_state.selected().and_then(|idx| visible.get(idx))
+                        else {
+                            continue;
+                        };
+                        match clip_path(&entry.content) {
+                            None => status = Some("Not a file path".to_string()),
+                            Some(path) if !path.exists() => {
+                                status = Some(format!("Path not found: {}", path.display()));
+                            }
+                            Some(path) => {
+                                // `O` opens the folder holding the path instead of the path itself.
+                                let target = match path.parent() {
+                                    Some(parent) if key.code == KeyCode::Char('O') => {
+                                        parent.to_path_buf()
+                                    }
+                                    _ => path,
+                                };
+                                in_background(&background_errors_tx, move || {
+                                    open_in_system(&target)
+                                });
+                            }
+                        }
+                    }
+                    KeyCode::Char('r') if !pick_mode => {
+                        if let Some(entry) = list_state.selected().and_then(|idx| visible.get(idx))
+                        {
+                            let reference = entry_reference(entry, &config);
+                            status = match set_clipboard(&reference, &capture.restored) {
+                                Ok(()) => after_copy(&history, entry, "copy_reference", &config)
+                                    .or_else(|| Some(format!("Copied \"{}\"", reference))),
+                                Err(e) => Some(format!("Copy failed: {}", e)),
+                            };
+                        }
+                    }
+                    KeyCode::Char('[' | ']') if !pick_mode => {
+                        let Some(entry) = list_state.selected().and_then(|idx| visible.get(idx))
+                        else {
+                            continue;
+                        };
+                        let first = key.code == KeyCode::Char('[');
+                        let line = match entry.content.lines().count() {
+                            _ if entry.image.is_some() => continue,
+                            0 => continue,
+                            1 => entry.content.as_str(),
+                            _ if first => entry.content.lines().next().unwrap_or(""),
+                            _ => entry.content.lines().last().unwrap_or(""),
+                        };
+                        let done = if line == entry.content {
+                            "Copied the entry"
+                        } else if first {
+                            "Copied the first line"
+                        } else {
+                            "Copied the last line"
+                        };
+                        status = match set_clipboard(line, &capture.restored) {
+                            Ok(()) => after_copy(&history, entry, "copy_line", &config)
+                                .or_else(|| Some(done.to_string())),
+                            Err(e) => Some(format!("Copy failed: {}", e)),
+                        };
+                    }
+                    KeyCode::Char('P') if !pick_mode => {
+                        if let Some(entry) = list_state.selected().and_then(|idx| visible.get(idx))
+                        {
+                            let result = match &config.paste_command {
+                                Some(command) => copy_entry(entry, &config, &capture.restored)
+                                    .map(|()| command.clone())
+                                    .map_err(|e| format!("Copy failed: {}", e)),
+                                None => {
+                                    Err("Set paste_command in config.json to paste".to_string())
                                 }
-                                list_state.select(Some(selected));
+                            };
+                            if result.is_ok() {
+                                status = after_copy(&history, entry, "paste", &config);
                             }
-                            KeyCode::Enter => {
-                                if let Some(idx) = list_state.selected() {
-                                    if let Some(entry) = history.lock().unwrap().get(len - 1 - idx)
-                                    {
-                                        let mut clipboard = Clipboard::new().unwrap();
-                                        clipboard.set_text(entry.content.clone()).unwrap();
+                            match result {
+                                // clipb is about to exit, so there is no UI to keep responsive.
+                                Ok(command) if config.exit_on_select => {
+                                    match run_paste_command(&command, command_timeout) {
+                                        Ok(()) => {
+                                            picked = Some(entry.content.clone());
+                                            break;
+                                        }
+                                        Err(e) => status = Some(e),
                                     }
                                 }
+                                Ok(command) => in_background(&background_errors_tx, move || {
+                                    run_paste_command(&command, command_timeout)
+                                }),
+                                Err(e) => status = Some(e),
+                            }
+                        }
+                    }
+                    KeyCode::Char('x') | KeyCode::Char('X') => {
+                        let action = if key.code == KeyCode::Char('X') {
+                            Destructive::Clear
+                        } else if let Some(entry) =
+                            list_state.selected().and_then(|idx| visible.get(idx))
+                        {
+                            Destructive::Delete { id: entry.id }
+                        } else {
+                            continue;
+                        };
+                        if config.confirm_destructive {
+                            input_mode = InputMode::Confirm(action);
+                            continue;
+                        }
+                        status = Some(action.apply(&mut history.lock().unwrap()));
+                    }
+                    KeyCode::Char('Q') if !pick_mode => {
+                        if let Some(entry) = list_state.selected().and_then(|idx| visible.get(idx))
+                            && entry.image.is_none()
+                        {
+                            input_mode = InputMode::Quoting { id: entry.id };
+                        }
+                        continue;
+                    }
+                    KeyCode::Char('w') => {
+                        if let Some(entry) = list_state.selected().and_then(|idx| visible.get(idx))
+                        {
+                            if entry.image.is_some() {
+                                status =
+                                    Some("Only text entries can be saved to a file".to_string());
+                            } else {
+                                input_mode = InputMode::Saving {
+                                    id: entry.id,
+                                    buffer: String::new(),
+                                };
                             }
-                            KeyCode::Char('/') => {
-                                input_mode = InputMode::Searching(String::new());
-                                list_state.select(Some(0));
+                        }
+                        continue;
+                    }
+                    KeyCode::Char('n') => {
+                        if let Some(entry) = list_state.selected().and_then(|idx| visible.get(idx))
+                        {
+                            input_mode = InputMode::Noting {
+                                id: entry.id,
+                                buffer: entry.note.clone().unwrap_or_default(),
+                            };
+                        }
+                        continue;
+                    }
+                    KeyCode::Char('l') => {
+                        if let Some(entry) = list_state.selected().and_then(|idx| visible.get(idx))
+                        {
+                            input_mode = InputMode::Labeling {
+                                id: entry.id,
+                                buffer: entry.label.clone().unwrap_or_default(),
+                            };
+                        }
+                        continue;
+                    }
+                    KeyCode::Tab | KeyCode::BackTab if tabs.len() > 1 => {
+                        active_tab = if key.code == KeyCode::Tab {
+                            (active_tab + 1) % tabs.len()
+                        } else {
+                            (active_tab + tabs.len() - 1) % tabs.len()
+                        };
+                        let mut hist = history.lock().unwrap();
+                        hist.flush();
+                        *hist = ClipHistory::load(&config, tabs[active_tab]);
+                        list_state.select(Some(0));
+                    }
+                    _ => {}
+                }
+            }
+
+            // Anything but `y` cancels.
+            if let InputMode::Confirm(_) = &input_mode
+                && let InputMode::Confirm(action) =
+                    std::mem::replace(&mut input_mode, InputMode::Normal)
+                && matches!(key.code, KeyCode::Char('y' | 'Y'))
+            {
+                status = Some(action.apply(&mut history.lock().unwrap()));
+            }
+
+            // Any other key cancels.
+            if let InputMode::Quoting { id } = input_mode {
+                input_mode = InputMode::Normal;
+                let (quote, action, done): (fn(&str) -> String, _, _) = match key.code {
+                    KeyCode::Char('s') => (shell_quote, "copy_shell", "Copied shell-quoted"),
+                    KeyCode::Char('m') => (
+                        markdown_fence,
+                        "copy_markdown",
+                        "Copied as a Markdown code block",
+                    ),
+                    _ => continue,
+                };
+                if let Some(entry) = hist.iter().find(|e| e.id == id) {
+                    let quoted = quote(&entry.content);
+                    status = match set_clipboard(&quoted, &capture.restored) {
+                        Ok(()) => after_copy(&history, entry, action, &config)
+                            .or_else(|| Some(done.to_string())),
+                        Err(e) => Some(format!("Copy failed: {}", e)),
+                    };
+                }
+            }
+
+            if let InputMode::Snippets(None) = &input_mode {
+                let selected = list_state.selected().unwrap_or(0);
+                match key.code {
+                    KeyCode::Esc | KeyCode::Char('q') => {
+                        input_mode = InputMode::Normal;
+                        list_state.select(Some(0));
+                    }
+                    KeyCode::Char('a' | 'e' | 'x') if config.read_only => {
+                        status = Some("Read-only: snippets can't be changed".to_string());
+                    }
+                    KeyCode::Char('a') => {
+                        input_mode = InputMode::Snippets(Some(SnippetEdit::Naming(SnippetDraft {
+                            index: None,
+                            name: String::new(),
+                            content: String::new(),
+                        })));
+                        continue;
+                    }
+                    KeyCode::Char('e') => {
+                        if let Some(snippet) = snippets.get(selected) {
+                            input_mode =
+                                InputMode::Snippets(Some(SnippetEdit::Naming(SnippetDraft {
+                                    index: Some(selected),
+                                    name: snippet.name.clone().unwrap_or_default(),
+                                    content: snippet.content.clone(),
+                                })));
+                        }
+                        continue;
+                    }
+                    KeyCode::Char('x') if selected < snippets.len() => {
+                        if config.confirm_destructive {
+                            input_mode = InputMode::Snippets(Some(SnippetEdit::Deleting {
+                                index: selected,
+                            }));
+                            continue;
+                        }
+                        snippets.remove(selected);
+                        status = Some(
+                            save_snippets(&snippets)
+                                .map_or_else(|e| e, |()| "Snippet deleted".to_string()),
+                        );
+                        list_state.select(Some(selected.min(snippets.len().saturating_sub(1))));
+                    }
+                    KeyCode::Down if selected + 1 < snippets.len() => {
+                        list_state.select(Some(selected + 1));
+                    }
+                    KeyCode::Up => list_state.select(Some(selected.saturating_sub(1))),
+                    KeyCode::Enter => {
+                        if let Some(snippet) = snippets.get(selected) {
+                            if !pick_mode
+                                && let Err(e) = set_clipboard(&snippet.content, &capture.restored)
+                            {
+                                status = Some(format!("Copy failed: {}", e));
+                            } else if pick_mode || config.exit_on_select {
+                                picked = Some(snippet.content.clone());
+                                break;
                             }
-                            _ => {}
                         }
                     }
                     _ => {}
                 }
+            }
 
-                if let InputMode::Searching(query) = &mut input_mode {
-                    match key.code {
-                        KeyCode::Esc => {
-                            input_mode = InputMode::Normal;
-                            list_state.select(Some(0));
+            if let InputMode::Snippets(Some(edit)) = &mut input_mode {
+                let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+                match edit {
+                    SnippetEdit::Deleting { index } => {
+                        let index = *index;
+                        input_mode = InputMode::Snippets(None);
+                        // Anything but `y` cancels.
+                        if matches!(key.code, KeyCode::Char('y' | 'Y')) {
+                            snippets.remove(index);
+                            status = Some(
+                                save_snippets(&snippets)
+                                    .map_or_else(|e| e, |()| "Snippet deleted".to_string()),
+                            );
+                            list_state.select(Some(index.min(snippets.len().saturating_sub(1))));
+                        }
+                    }
+                    SnippetEdit::Naming(draft) => match key.code {
+                        KeyCode::Esc => input_mode = InputMode::Snippets(None),
+                        KeyCode::Enter => {
+                            if let InputMode::Snippets(Some(SnippetEdit::Naming(draft))) =
+                                std::mem::replace(&mut input_mode, InputMode::Snippets(None))
+                            {
+                                input_mode = InputMode::Snippets(Some(SnippetEdit::Writing(draft)));
+                            }
+                        }
+                        KeyCode::Char(c) => draft.name.push(c),
+                        KeyCode::Backspace => {
+                            draft.name.pop();
+                        }
+                        _ => {}
+                    },
+                    SnippetEdit::Writing(draft) => match key.code {
+                        KeyCode::Esc => input_mode = InputMode::Snippets(None),
+                        KeyCode::Enter if draft.content.trim().is_empty() => {
+                            status = Some("A snippet needs some content".to_string());
                         }
                         KeyCode::Enter => {
-                            let hist = history.lock().unwrap();
-                            let filtered: Vec<_> = hist
-                                .iter()
-                                .rev()
-                                .filter(|e| {
-                                    e.content.to_lowercase().contains(&query.to_lowercase())
-                                })
-                                .collect();
-
-                            if let Some(idx) = list_state.selected() {
-                                if let Some(entry) = filtered.get(idx) {
-                                    let mut clipboard = Clipboard::new().unwrap();
-                                    clipboard.set_text(entry.content.clone()).unwrap();
+                            let name = draft.name.trim();
+                            let snippet = Snippet {
+                                name: (!name.is_empty()).then(|| name.to_string()),
+                                content: std::mem::take(&mut draft.content),
+                            };
+                            let index = match draft.index {
+                                Some(index) => {
+                                    snippets[index] = snippet;
+                                    index
                                 }
+                                None => {
+                                    snippets.push(snippet);
+                                    snippets.len() - 1
+                                }
+                            };
+                            status = Some(
+                                save_snippets(&snippets)
+                                    .map_or_else(|e| e, |()| "Snippet saved".to_string()),
+                            );
+                            input_mode = InputMode::Snippets(None);
+                            list_state.select(Some(index));
+                        }
+                        KeyCode::Char('j') if ctrl => draft.content.push('\n'),
+                        KeyCode::Char(c) if !ctrl => draft.content.push(c),
+                        KeyCode::Backspace => {
+                            draft.content.pop();
+                        }
+                        _ => {}
+                    },
+                }
+                continue;
+            }
+
+            if let InputMode::Labeling { id, buffer } = &mut input_mode {
+                match key.code {
+                    KeyCode::Esc => input_mode = InputMode::Normal,
+                    KeyCode::Enter => {
+                        let label = buffer.trim();
+                        let mut hist = history.lock().unwrap();
+                        if let Some(entry) = hist.find_mut(*id) {
+                            entry.label = (!label.is_empty()).then(|| label.to_string());
+                            let pinned = entry.pinned;
+                            hist.save();
+                            if pinned {
+                                hist.save_pinned();
                             }
+                        }
+                        drop(hist);
+                        input_mode = InputMode::Normal;
+                    }
+                    KeyCode::Char(c) => buffer.push(c),
+                    KeyCode::Backspace => {
+                        buffer.pop();
+                    }
+                    _ => {}
+                }
+            }
 
-                            input_mode = InputMode::Normal;
-                            list_state.select(Some(0));
+            if let InputMode::Saving { id, buffer } = &mut input_mode {
+                match key.code {
+                    KeyCode::Esc => input_mode = InputMode::Normal,
+                    KeyCode::Enter if buffer.trim().is_empty() => input_mode = InputMode::Normal,
+                    KeyCode::Enter => {
+                        let id = *id;
+                        let path = expand_path(buffer.trim());
+                        input_mode = InputMode::Normal;
+                        if path.is_dir() {
+                            status = Some(format!("{} is a folder", path.display()));
+                        } else if path.exists() && config.confirm_destructive {
+                            input_mode = InputMode::Confirm(Destructive::Overwrite { id, path });
+                        } else if let Some(entry) = hist.iter().find(|e| e.id == id) {
+                            status = Some(write_entry(entry, &path));
                         }
-                        KeyCode::Down => {
-                            let hist = history.lock().unwrap();
-                            let filtered_len = hist
-                                .iter()
-                                .filter(|e| {
-                                    e.content.to_lowercase().contains(&query.to_lowercase())
-                                })
-                                .count();
-
-                            if filtered_len > 0 {
-                                let selected = list_state.selected().unwrap_or(0);
-                                if selected + 1 < filtered_len {
-                                    list_state.select(Some(selected + 1));
-                                }
+                    }
+                    KeyCode::Char(c) => buffer.push(c),
+                    KeyCode::Backspace => {
+                        buffer.pop();
+                    }
+                    _ => {}
+                }
+            }
+
+            if let InputMode::Noting { id, buffer } = &mut input_mode {
+                match key.code {
+                    KeyCode::Esc => input_mode = InputMode::Normal,
+                    KeyCode::Enter => {
+                        let note = buffer.trim();
+                        let mut hist = history.lock().unwrap();
+                        if let Some(entry) = hist.find_mut(*id) {
+                            entry.note = (!note.is_empty()).then(|| note.to_string());
+                            let pinned = entry.pinned;
+                            hist.save();
+                            // Pins that fall out of the history come back from the pinned file.
+                            if pinned {
+                                hist.save_pinned();
                             }
                         }
-                        KeyCode::Up => {
-                            let mut selected = list_state.selected().unwrap_or(0);
-                            if selected > 0 {
-                                selected -= 1;
+                        drop(hist);
+                        input_mode = InputMode::Normal;
+                    }
+                    KeyCode::Char(c) => buffer.push(c),
+                    KeyCode::Backspace => {
+                        buffer.pop();
+                    }
+                    _ => {}
+                }
+            }
+
+            if let InputMode::Searching(query) = &mut input_mode {
+                let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+                match key.code {
+                    KeyCode::Esc => {
+                        remember_query(&mut search_history, query);
+                        let (id, offset) = pre_search.take().unwrap_or((None, 0));
+                        let all = visible_entries(
+                            &hist,
+                            &InputMode::Normal,
+                            search_options,
+                            sort,
+                            &config,
+                        );
+                        match id.and_then(|id| all.iter().position(|e| e.id == id)) {
+                            Some(idx) => {
+                                list_state.select(Some(idx));
+                                *list_state.offset_mut() = offset;
                             }
-                            list_state.select(Some(selected));
+                            None => list_state = ListState::default().with_selected(Some(0)),
                         }
-                        KeyCode::Char(c) => {
-                            query.push(c);
-                            list_state.select(Some(0));
+                        input_mode = InputMode::Normal;
+                    }
+                    // Ctrl+P/Ctrl+N walk previous queries like shell history; so do Up/Down while
+                    // the query is empty, since there are no results to move through then.
+                    KeyCode::Char('p') if ctrl => {
+                        recall_query(&search_history, &mut search_recall, query, true);
+                        list_state.select(Some(0));
+                    }
+                    KeyCode::Char('w') if ctrl => {
+                        search_options.whole_word = !search_options.whole_word;
+                        list_state.select(Some(0));
+                    }
+                    KeyCode::Char('s') if ctrl => {
+                        search_options.collapse_whitespace = !search_options.collapse_whitespace;
+                        list_state.select(Some(0));
+                    }
+                    KeyCode::Char('n') if ctrl => {
+                        recall_query(&search_history, &mut search_recall, query, false);
+                        list_state.select(Some(0));
+                    }
+                    KeyCode::Up | KeyCode::Down if query.is_empty() => {
+                        let older = key.code == KeyCode::Up;
+                        recall_query(&search_history, &mut search_recall, query, older);
+                        list_state.select(Some(0));
+                    }
+                    KeyCode::Enter => {
+                        remember_query(&mut search_history, query);
+                        pre_search = None;
+                        let mut copied = None;
+                        if let Some(idx) = list_state.selected()
+                            && let Some(entry) = visible.get(idx)
+                        {
+                            if !pick_mode
+                                && let Err(e) = copy_entry(entry, &config, &capture.restored)
+                            {
+                                status = Some(format!("Copy failed: {}", e));
+                            } else {
+                                status = after_copy(&history, entry, "copy", &config);
+                                if pick_mode || config.exit_on_select {
+                                    picked = Some(entry.content.clone());
+                                    break;
+                                }
+                            }
+                            // A burned entry is gone, so the list opens at the top instead.
+                            if !entry.burn {
+                                copied = Some(entry.id);
+                            }
                         }
-                        KeyCode::Backspace => {
-                            query.pop();
-                            list_state.select(Some(0));
+
+                        // The full list opens on the entry that was picked from the matches.
+                        let all = visible_entries(
+                            &hist,
+                            &InputMode::Normal,
+                            search_options,
+                            sort,
+                            &config,
+                        );
+                        match copied.and_then(|id| all.iter().position(|e| e.id == id)) {
+                            Some(idx) => {
+                                list_state.select(Some(idx));
+                                let row = display_row(&all, idx, sort, &config);
+                                *list_state.offset_mut() = row.saturating_sub(cursor_row);
+                            }
+                            None => list_state = ListState::default().with_selected(Some(0)),
                         }
-                        _ => {}
+                        input_mode = InputMode::Normal;
+                    }
+                    KeyCode::Down => {
+                        let selected = list_state.selected().unwrap_or(0);
+                        if selected + 1 < len {
+                            list_state.select(Some(selected + 1));
+                        }
+                    }
+                    KeyCode::Up => {
+                        let selected = list_state.selected().unwrap_or(0).saturating_sub(1);
+                        list_state.select(Some(selected));
+                    }
+                    KeyCode::Char(c) => {
+                        query.push(c);
+                        search_recall = None;
+                        list_state.select(Some(0));
+                    }
+                    KeyCode::Backspace => {
+                        query.pop();
+                        search_recall = None;
+                        list_state.select(Some(0));
                     }
+                    _ => {}
                 }
             }
         }
     }
 
+    // Let an in-flight capture land before the final flush. Everything else was written as it
+    // happened, so the file is only rewritten if something is still pending.
+    capture.stop();
+    if let Some(poller) = poller {
+        let _ = poller.join();
+    }
+    let mut shared = history.lock().unwrap();
+    shared.flush();
+    // Queued writes drain when the history is dropped, which the API thread's handle may stop.
+    shared.wait_for_writes();
+    // Said once the terminal is back, as there is no footer left to show it in.
+    let save_error = shared.take_write_error();
+    drop(shared);
+    if config.initial_selection == InitialSelection::LastViewed && !config.read_only {
+        ui_state.last_viewed = list_state
+            .selected()
+            .and_then(|idx| {
+                visible_entries(&hist, &input_mode, search_options, sort, &config)
+                    .get(idx)
+                    .copied()
+            })
+            .map(ViewedEntry::of);
+        ui_state.last_viewed_row = cursor_row;
+        save_ui_state(&ui_state);
+    }
+
     disable_raw_mode()?;
-    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    if config.alternate_screen {
+        execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    } else {
+        // Continue below the last frame rather than over it.
+        let bottom = terminal.get_frame().area().bottom();
+        execute!(terminal.backend_mut(), MoveTo(0, bottom.saturating_sub(1)))?;
+        writeln!(terminal.backend_mut())?;
+    }
     terminal.show_cursor()?;
+    if let Some(e) = save_error {
+        eprintln!("clipb: {}", e);
+    }
+
+    if pick_mode {
+        match picked {
+            Some(content) => {
+                let mut stdout = io::stdout();
+                stdout.write_all(content.as_bytes())?;
+                stdout.flush()?;
+            }
+            None => std::process::exit(1),
+        }
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clip_path_skips_comments_and_prose() {
+        let home = dirs::home_dir().unwrap();
+        let cases = [
+            ("/usr/bin/env", Some(PathBuf::from("/usr/bin/env"))),
+            (
+                "  /tmp/My Files/a b.txt\n",
+                Some(PathBuf::from("/tmp/My Files/a b.txt")),
+            ),
+            ("file:///etc/hosts", Some(PathBuf::from("/etc/hosts"))),
+            ("~/notes.md", Some(home.join("notes.md"))),
+            (
+                "C:\\Program Files\\x",
+                Some(PathBuf::from("C:\\Program Files\\x")),
+            ),
+            ("// TODO: tidy", None),
+            ("/* comment */", None),
+            ("/ not a path", None),
+            ("~/ later", None),
+            ("/", None),
+            ("relative/path", None),
+            ("/tmp/a\n/tmp/b", None),
+        ];
+        for (content, path) in cases {
+            assert_eq!(clip_path(content), path, "{:?}", content);
+        }
+    }
+}
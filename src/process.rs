@@ -0,0 +1,63 @@
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// `command` as run by the platform's shell (`sh -c`, or `cmd /C` on Windows).
+pub fn shell_command(command: &str) -> Command {
+    let (shell, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    let mut shell = Command::new(shell);
+    shell.args([flag, command]);
+    shell
+}
+
+/// Runs `command` to completion with no stdio (its output would land on top of a TUI), killing
+/// it if it takes longer than `timeout`. `what` names it in errors.
+pub fn run_with_timeout(
+    mut command: Command,
+    what: &str,
+    timeout: Option<Duration>,
+) -> Result<(), String> {
+    let child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("{} failed: {}", what, e))?;
+    match wait_with_timeout(child, what, timeout)? {
+        status if status.success() => Ok(()),
+        status => Err(format!("{} failed ({})", what, status)),
+    }
+}
+
+// Waits for `child` to exit, killing it if it takes longer than `timeout`.
+pub(crate) fn wait_with_timeout(
+    mut child: Child,
+    what: &str,
+    timeout: Option<Duration>,
+) -> Result<ExitStatus, String> {
+    const POLL: Duration = Duration::from_millis(20);
+    let started = Instant::now();
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => return Ok(status),
+            Ok(None)
+                if let Some(timeout) = timeout
+                    && started.elapsed() >= timeout =>
+            {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!(
+                    "{} timed out after {}s and was stopped",
+                    what,
+                    timeout.as_secs_f32()
+                ));
+            }
+            Ok(None) => thread::sleep(POLL),
+            Err(e) => return Err(format!("{} failed: {}", what, e)),
+        }
+    }
+}
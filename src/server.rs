@@ -0,0 +1,235 @@
+use crate::config::{Config, debug_log};
+use crate::history::{ClipHistory, Entry, now_timestamp};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// Requests beyond these sizes, or slower than the timeout, are cut off.
+const MAX_HEAD_BYTES: u64 = 64 * 1024;
+const MAX_BODY_BYTES: usize = 16 * 1024 * 1024;
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Opens the API's port on the loopback interface only.
+pub fn bind(port: u16) -> io::Result<TcpListener> {
+    TcpListener::bind(("127.0.0.1", port))
+}
+
+/// Answers HTTP requests on `listener`, one connection at a time, for as long as it accepts
+/// them. Every request must carry `Authorization: Bearer <token>`, and every response is JSON.
+///
+/// - `GET /history`: all entries, newest first, each with its number `n` (1 is the newest,
+///   as in `clipb search` and `clipb get`) and its `id`.
+/// - `GET /entry/<id>`: one entry by its `id`, or 404. Ids stay with their entries while this
+///   clipb runs, however the list moves around them; numbers shift with every capture.
+/// - `POST /clip` with `{"content": "..."}`: stores the text as if it had just been captured
+///   and answers 201 with the entry. Text the poll thread wouldn't store (`skip_empty`,
+///   `min_entry_chars`) is refused with 422, and everything with 403 under `read_only`.
+///
+/// `on_clip` runs after each clip is stored, so a UI can pick it up.
+pub fn serve(
+    listener: TcpListener,
+    history: Arc<Mutex<ClipHistory>>,
+    config: Config,
+    token: String,
+    on_clip: impl Fn(),
+) {
+    for stream in listener.incoming() {
+        let handled = stream.and_then(|stream| {
+            stream.set_read_timeout(Some(TIMEOUT))?;
+            stream.set_write_timeout(Some(TIMEOUT))?;
+            let (status, body) = match read_request(&stream)? {
+                Some(request) => answer(&request, &history, &config, &token, &on_clip),
+                None => (413, error("request too large")),
+            };
+            respond(&stream, status, &body)
+        });
+        if let Err(e) = handled {
+            debug_log(&format!("API request failed: {}", e));
+        }
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    authorization: Option<String>,
+    body: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+struct NewClip {
+    content: String,
+}
+
+// `None` when the body is over `MAX_BODY_BYTES`.
+fn read_request(stream: &TcpStream) -> io::Result<Option<Request>> {
+    let mut reader = BufReader::new(stream.take(MAX_HEAD_BYTES));
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or("").to_string();
+    let path = parts.next().unwrap_or("").to_string();
+
+    let mut length = 0;
+    let mut authorization = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            break;
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            length = value
+                .parse()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "bad Content-Length"))?;
+        } else if name.eq_ignore_ascii_case("authorization") {
+            authorization = Some(value.to_string());
+        }
+    }
+    if length > MAX_BODY_BYTES {
+        return Ok(None);
+    }
+
+    // Lifts the head's limit to cover the body; bytes the reader already buffered count too.
+    reader.get_mut().set_limit(length as u64);
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(Some(Request {
+        method,
+        path,
+        authorization,
+        body,
+    }))
+}
+
+fn answer(
+    request: &Request,
+    history: &Mutex<ClipHistory>,
+    config: &Config,
+    token: &str,
+    on_clip: &impl Fn(),
+) -> (u16, Value) {
+    let bearer = request
+        .authorization
+        .as_deref()
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if bearer != Some(token) {
+        return (401, error("missing or wrong token"));
+    }
+    let path = request.path.split('?').next().unwrap_or("");
+    let method = request.method.as_str();
+    match path {
+        "/history" if method == "GET" => {
+            let hist = history.lock().unwrap();
+            let entries = hist.entries().iter().rev().zip(1..);
+            let list: Vec<Value> = entries.map(|(e, n)| numbered(e, n)).collect();
+            (200, Value::Array(list))
+        }
+        "/clip" if method == "POST" => {
+            if config.read_only {
+                return (403, error("clipb is running read-only"));
+            }
+            let Ok(clip) = serde_json::from_slice::<NewClip>(&request.body) else {
+                return (
+                    400,
+                    error("expected a JSON object with a \"content\" string"),
+                );
+            };
+            let content = config.captured_text(&clip.content).into_owned();
+            if !config.keeps_text(&content) {
+                return (
+                    422,
+                    error("the clip is empty or shorter than min_entry_chars"),
+                );
+            }
+            let entry = Entry {
+                timestamp: now_timestamp(config.timestamp_utc),
+                content: content.clone(),
+                count: 1,
+                ..Default::default()
+            };
+            let mut hist = history.lock().unwrap();
+            hist.push(entry, config);
+            // Usually the new top entry, but `pinned_match` may have left an older one in place.
+            let stored = hist
+                .entries()
+                .iter()
+                .rev()
+                .zip(1..)
+                .find(|(e, _)| e.content == content && e.image.is_none())
+                .map(|(e, n)| numbered(e, n));
+            drop(hist);
+            on_clip();
+            (201, stored.unwrap_or(Value::Null))
+        }
+        "/history" | "/clip" => (405, error("method not allowed")),
+        _ => match path.strip_prefix("/entry/") {
+            Some(_) if method != "GET" => (405, error("method not allowed")),
+            Some(id) => {
+                let Ok(id) = id.parse::<u64>() else {
+                    return (400, error("entry ids are numbers, as listed by /history"));
+                };
+                let hist = history.lock().unwrap();
+                let entry = hist
+                    .entries()
+                    .iter()
+                    .rev()
+                    .zip(1..)
+                    .find(|(e, _)| e.id == id);
+                match entry {
+                    Some((e, n)) => (200, numbered(e, n)),
+                    None => (404, error("no such entry")),
+                }
+            }
+            None => (404, error("not found")),
+        },
+    }
+}
+
+// `entry` as JSON, with its `id` and its number in the newest-first list added as `n`.
+fn numbered(entry: &Entry, n: usize) -> Value {
+    let mut value = serde_json::to_value(entry).unwrap_or(Value::Null);
+    if let Value::Object(fields) = &mut value {
+        fields.insert("id".to_string(), json!(entry.id));
+        fields.insert("n".to_string(), json!(n));
+    }
+    value
+}
+
+fn error(message: &str) -> Value {
+    json!({ "error": message })
+}
+
+fn respond(mut stream: &TcpStream, status: u16, body: &Value) -> io::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        201 => "Created",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        422 => "Unprocessable Content",
+        _ => "Payload Too Large",
+    };
+    let body = body.to_string();
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
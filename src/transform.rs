@@ -0,0 +1,351 @@
+fn base64_value(byte: u8) -> Option<u32> {
+    match byte {
+        b'A'..=b'Z' => Some((byte - b'A') as u32),
+        b'a'..=b'z' => Some((byte - b'a') as u32 + 26),
+        b'0'..=b'9' => Some((byte - b'0') as u32 + 52),
+        b'+' | b'-' => Some(62),
+        b'/' | b'_' => Some(63),
+        _ => None,
+    }
+}
+
+/// Decodes base64 in the standard or URL-safe alphabet, with or without padding. Whitespace is
+/// ignored.
+pub fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let clean: Vec<u8> = text.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    let data = clean
+        .strip_suffix(b"==")
+        .or_else(|| clean.strip_suffix(b"="))
+        .unwrap_or(&clean);
+    if data.is_empty() || data.len() % 4 == 1 {
+        return None;
+    }
+
+    let mut out = Vec::with_capacity(data.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut nbits = 0;
+    for &byte in data {
+        bits = (bits << 6) | base64_value(byte)?;
+        nbits += 6;
+        if nbits >= 8 {
+            nbits -= 8;
+            out.push((bits >> nbits) as u8);
+            bits &= (1 << nbits) - 1;
+        }
+    }
+    Some(out)
+}
+
+/// Encodes `bytes` as padded base64 in the standard alphabet.
+pub fn encode_base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let bits = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |bits, (i, &b)| bits | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(bits >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Decodes a run of hex digit pairs, optionally prefixed with `0x`. Whitespace is ignored.
+pub fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    let clean: Vec<u8> = text.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    let clean = clean.strip_prefix(b"0x").unwrap_or(&clean);
+    if clean.is_empty() || !clean.len().is_multiple_of(2) {
+        return None;
+    }
+    clean
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+/// Classic `offset  hex bytes  |ascii|` layout, 16 bytes per line.
+pub fn hex_dump(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for (i, chunk) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = chunk
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        out.push_str(&format!(
+            "{:08x}  {:<47}  |{}|\n",
+            i * 16,
+            hex.join(" "),
+            ascii
+        ));
+    }
+    out
+}
+
+/// Removes ANSI escape sequences (colors, cursor movement, OSC titles and hyperlinks, ...),
+/// keeping the text around them.
+pub fn strip_ansi(text: &str) -> String {
+    enum State {
+        Text,
+        Escape,
+        // Control sequence: parameters and intermediates up to a final byte in `@..=~`.
+        Csi,
+        // OSC, DCS and friends: everything up to BEL (OSC only) or ESC `\`.
+        String { bell_ends: bool },
+        StringEscape { bell_ends: bool },
+    }
+
+    let mut out = String::with_capacity(text.len());
+    let mut state = State::Text;
+    for c in text.chars() {
+        state = match state {
+            State::Text => match c {
+                '\x1b' => State::Escape,
+                '\u{9b}' => State::Csi,
+                _ => {
+                    out.push(c);
+                    State::Text
+                }
+            },
+            State::Escape => match c {
+                '[' => State::Csi,
+                ']' => State::String { bell_ends: true },
+                'P' | 'X' | '^' | '_' => State::String { bell_ends: false },
+                // Charset designations (`ESC ( B`) take one more character; other escapes such
+                // as `ESC 7` end here.
+                '(' | ')' | '*' | '+' => State::Escape,
+                _ => State::Text,
+            },
+            State::Csi => match c {
+                '@'..='~' => State::Text,
+                _ => State::Csi,
+            },
+            State::String { bell_ends } => match c {
+                '\x07' if bell_ends => State::Text,
+                '\x1b' => State::StringEscape { bell_ends },
+                _ => State::String { bell_ends },
+            },
+            State::StringEscape { bell_ends } => match c {
+                '\\' => State::Text,
+                _ => State::String { bell_ends },
+            },
+        };
+    }
+    out
+}
+
+/// Wraps `text` in single quotes for a POSIX shell, so it reaches a command as one argument
+/// exactly as written. Embedded single quotes become `'\''`; newlines, `$`, backticks and
+/// backslashes need nothing inside single quotes.
+pub fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+/// Puts `text` in a Markdown code block. The fence is one backtick longer than the longest run
+/// of backticks in `text` (and at least three), so nothing inside can close it early.
+pub fn markdown_fence(text: &str) -> String {
+    let mut longest = 0;
+    let mut run = 0;
+    for c in text.chars() {
+        run = if c == '`' { run + 1 } else { 0 };
+        longest = longest.max(run);
+    }
+    let fence = "`".repeat((longest + 1).max(3));
+    let newline = if text.ends_with('\n') || text.is_empty() {
+        ""
+    } else {
+        "\n"
+    };
+    format!("{}\n{}{}{}", fence, text, newline, fence)
+}
+
+/// Replaces each `{name}` in `template` with the value paired with `name` in `fields`. Unknown
+/// names and unmatched braces are left as they are.
+pub fn fill_template(template: &str, fields: &[(&str, &str)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        let after = &rest[open + 1..];
+        let value = after.find('}').and_then(|close| {
+            let name = &after[..close];
+            let (_, value) = fields.iter().find(|(field, _)| *field == name)?;
+            Some((value, close))
+        });
+        match value {
+            Some((value, close)) => {
+                out.push_str(value);
+                rest = &after[close + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// One piece of a template: literal text, or the name inside a `{name}` placeholder.
+#[derive(Debug, PartialEq)]
+pub enum Segment<'a> {
+    Literal(&'a str),
+    Field(&'a str),
+}
+
+/// Splits `template` into literal text and `{name}` placeholders. An unmatched `{` is literal.
+pub fn template_segments(template: &str) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        let Some(close) = rest[open..].find('}').map(|close| open + close) else {
+            break;
+        };
+        if open > 0 {
+            segments.push(Segment::Literal(&rest[..open]));
+        }
+        segments.push(Segment::Field(&rest[open + 1..close]));
+        rest = &rest[close + 1..];
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Literal(rest));
+    }
+    segments
+}
+
+/// The 64-bit FNV-1a hash of `bytes`. Unlike std's hashers it is fixed, so it can name things
+/// that outlive a run.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// How alike two texts are, from 0.0 to 1.0: one minus their edit distance over the longer
+/// length, in chars. Only the first `limit` chars of each are compared, to bound the cost.
+pub fn similarity(a: &str, b: &str, limit: usize) -> f64 {
+    let a: Vec<char> = a.chars().take(limit).collect();
+    let b: Vec<char> = b.chars().take(limit).collect();
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+    // Levenshtein distance, one row at a time.
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    1.0 - row[b.len()] as f64 / longest as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_ansi_removes_common_sequences() {
+        let cases = [
+            ("\x1b[1;31mred\x1b[0m plain", "red plain"),
+            ("\x1b[?25l\x1b[2J\x1b[H top", " top"),
+            ("\u{9b}32mgreen", "green"),
+            ("\x1b]0;title\x07text", "text"),
+            (
+                "\x1b]8;;https://example.com\x1b\\link\x1b]8;;\x1b\\",
+                "link",
+            ),
+            ("\x1bPq#0;1\x1b\\after", "after"),
+            ("\x1b(Bcharset", "charset"),
+            ("\x1b7saved\x1b8", "saved"),
+            ("cut off\x1b[3", "cut off"),
+            ("tabs\tand é\r\n", "tabs\tand é\r\n"),
+        ];
+        for (text, stripped) in cases {
+            assert_eq!(strip_ansi(text), stripped, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn fill_template_substitutes_known_fields_only() {
+        let fields = [
+            ("timestamp", "2024-03-15 14:22"),
+            ("content", "say {label}"),
+            ("label", ""),
+        ];
+        let fill = |template| fill_template(template, &fields);
+        assert_eq!(
+            fill("{timestamp}: {content}"),
+            "2024-03-15 14:22: say {label}"
+        );
+        assert_eq!(
+            fill("copied at {timestamp}{label}"),
+            "copied at 2024-03-15 14:22"
+        );
+        assert_eq!(
+            fill("{timestamp} {timestamp}"),
+            "2024-03-15 14:22 2024-03-15 14:22"
+        );
+        assert_eq!(fill("{source} {nope}"), "{source} {nope}");
+        assert_eq!(fill("{{timestamp}}"), "{2024-03-15 14:22}");
+        assert_eq!(fill("{timestamp"), "{timestamp");
+        assert_eq!(fill("no fields"), "no fields");
+    }
+
+    #[test]
+    fn shell_quote_survives_the_shell() {
+        let cases = [
+            ("plain", "'plain'"),
+            ("", "''"),
+            ("it's", r"'it'\''s'"),
+            ("''", r"''\'''\'''"),
+            ("a\nb $HOME `id` \\ \"q\"", "'a\nb $HOME `id` \\ \"q\"'"),
+        ];
+        for (text, quoted) in cases {
+            assert_eq!(shell_quote(text), quoted);
+            if cfg!(unix) {
+                let output = std::process::Command::new("sh")
+                    .args(["-c", &format!("printf %s {}", quoted)])
+                    .output()
+                    .unwrap();
+                assert_eq!(String::from_utf8_lossy(&output.stdout), text);
+            }
+        }
+    }
+
+    #[test]
+    fn fnv1a_matches_the_reference_values() {
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn markdown_fence_outgrows_backticks_inside() {
+        assert_eq!(markdown_fence("code"), "```\ncode\n```");
+        assert_eq!(markdown_fence("code\n"), "```\ncode\n```");
+        assert_eq!(markdown_fence(""), "```\n```");
+        assert_eq!(markdown_fence("use ``` here"), "````\nuse ``` here\n````");
+        assert_eq!(
+            markdown_fence("`a` ````` b\n"),
+            "``````\n`a` ````` b\n``````"
+        );
+    }
+}
@@ -0,0 +1,43 @@
+use crate::config::{data_path, debug_log};
+use crate::history::{Entry, now_timestamp};
+use crate::transform::fnv1a;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+
+/// One line of `usage.json`: an entry copied back out of clipb, and how.
+#[derive(Serialize, Deserialize)]
+pub struct Use {
+    /// When it was copied; see [`now_timestamp`].
+    pub time: String,
+    /// What was done with it: `copy` (`Enter`, in a search too), `copy_plain`, `copy_line`
+    /// (`[`/`]`), `copy_shell`, `copy_markdown`, `copy_reference` or `paste`.
+    pub action: String,
+    /// The entry's own timestamp, identifying it in the history.
+    pub captured: String,
+    /// [`fnv1a`] of the content, as 16 hex digits: enough to tell uses of the same text apart
+    /// from others without the log keeping a copy of everything pasted.
+    pub content_hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+}
+
+/// Appends a [`Use`] of `entry` to `usage.json` in the data directory. The log is JSON Lines
+/// and only ever appended to, so it is kept apart from the history file.
+pub fn log(entry: &Entry, action: &str, utc: bool) {
+    let record = Use {
+        time: now_timestamp(utc),
+        action: action.to_string(),
+        captured: entry.timestamp.clone(),
+        content_hash: format!("{:016x}", fnv1a(entry.content.as_bytes())),
+        image: entry.image.clone(),
+    };
+    let written = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(data_path("usage.json"))
+        .and_then(|mut file| writeln!(file, "{}", serde_json::to_string(&record).unwrap()));
+    if let Err(e) = written {
+        debug_log(&format!("usage log not written: {}", e));
+    }
+}